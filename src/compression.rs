@@ -0,0 +1,272 @@
+use std::io::{self, BufReader, Read, Write};
+
+/// Compression formats for input and output files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+    Bzip2,
+}
+
+impl Compression {
+    /// File extension, including the leading dot, or empty for no compression.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::None => "",
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+            Compression::Xz => ".xz",
+            Compression::Bzip2 => ".bz2",
+        }
+    }
+
+    /// All formats, including no compression.
+    pub const ALL: [Compression; 5] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Zstd,
+        Compression::Xz,
+        Compression::Bzip2,
+    ];
+
+    /// Recognise the compression format from the first bytes of a file.
+    /// Anything without a known magic number is taken to be uncompressed.
+    pub fn sniff(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else if magic.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Wrap a reader in a decoder for this format.
+    /// Concatenated streams are read as one, as produced by parallel compressors.
+    pub fn decoder<R: Read>(&self, reader: R) -> io::Result<Decoder<R>> {
+        Ok(match self {
+            Compression::None => Decoder::None(reader),
+            Compression::Gzip => Decoder::Gzip(flate2::read::MultiGzDecoder::new(reader)),
+            Compression::Zstd => Decoder::Zstd(zstd::Decoder::new(reader)?),
+            Compression::Xz => Decoder::Xz(xz2::read::XzDecoder::new_multi_decoder(reader)),
+            Compression::Bzip2 => Decoder::Bzip2(bzip2::read::MultiBzDecoder::new(reader)),
+        })
+    }
+
+    /// Range of levels accepted by the encoder, and the default.
+    /// Defaults are the usual ones for each format, except gzip which has always used its best compression here.
+    fn levels(&self) -> (u32, u32, u32) {
+        match self {
+            Compression::None => (0, 0, 0),
+            Compression::Gzip => (0, 9, 9),
+            Compression::Zstd => (1, 22, 3),
+            Compression::Xz => (0, 9, 6),
+            Compression::Bzip2 => (1, 9, 9),
+        }
+    }
+
+    /// Check a compression level is valid for this format, returning the level to use.
+    /// If `level` is None, the default for the format is used.
+    pub fn check_level(&self, level: Option<u32>) -> anyhow::Result<u32> {
+        let (min, max, default) = self.levels();
+        let level = level.unwrap_or(default);
+
+        if *self != Compression::None && !(min..=max).contains(&level) {
+            return Err(anyhow::format_err!(
+                "Compression level {} out of range for {:?}, expected {} to {}",
+                level,
+                self,
+                min,
+                max
+            ));
+        }
+
+        Ok(level)
+    }
+
+    /// Wrap a writer in an encoder for this format.
+    /// If `level` is None, the default for the format is used.
+    pub fn encoder<W: Write>(&self, writer: W, level: Option<u32>) -> anyhow::Result<Encoder<W>> {
+        let level = self.check_level(level)?;
+
+        Ok(match self {
+            Compression::None => Encoder::None(writer),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::new(level),
+            )),
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, level as i32)?;
+                // Use all CPUs, as compression is usually the bottleneck.
+                if let Ok(threads) = std::thread::available_parallelism() {
+                    encoder.multithread(threads.get() as u32)?;
+                }
+                Encoder::Zstd(encoder)
+            }
+            Compression::Xz => Encoder::Xz(xz2::write::XzEncoder::new(writer, level)),
+            Compression::Bzip2 => Encoder::Bzip2(bzip2::write::BzEncoder::new(
+                writer,
+                bzip2::Compression::new(level),
+            )),
+        })
+    }
+}
+
+/// A writer that compresses to the inner writer.
+/// Must be explicitly finished, so that trailers are written and errors aren't lost on drop.
+pub enum Encoder<W: Write> {
+    None(W),
+    Gzip(flate2::write::GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+    Xz(xz2::write::XzEncoder<W>),
+    Bzip2(bzip2::write::BzEncoder<W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Write any remaining data and trailers, and return the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::None(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Zstd(encoder) => encoder.finish(),
+            Encoder::Xz(encoder) => encoder.finish(),
+            Encoder::Bzip2(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::None(writer) => writer.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+            Encoder::Xz(encoder) => encoder.write(buf),
+            Encoder::Bzip2(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::None(writer) => writer.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+            Encoder::Xz(encoder) => encoder.flush(),
+            Encoder::Bzip2(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A reader that decompresses from the inner reader.
+pub enum Decoder<R: Read> {
+    None(R),
+    Gzip(flate2::read::MultiGzDecoder<R>),
+    Zstd(zstd::Decoder<'static, BufReader<R>>),
+    Xz(xz2::read::XzDecoder<R>),
+    Bzip2(bzip2::read::MultiBzDecoder<R>),
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Decoder::None(reader) => reader.read(buf),
+            Decoder::Gzip(decoder) => decoder.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+            Decoder::Xz(decoder) => decoder.read(buf),
+            Decoder::Bzip2(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(compression: Compression, data: &[u8], level: Option<u32>) -> Vec<u8> {
+        let mut encoder = compression.encoder(vec![], level).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decompress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        compression
+            .decoder(data)
+            .unwrap()
+            .read_to_end(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = "{\"DOI\": \"10.5555/abc\"}\n".repeat(1000);
+
+        for compression in Compression::ALL {
+            let compressed = compress(compression, data.as_bytes(), None);
+            if compression != Compression::None {
+                assert!(compressed.len() < data.len(), "{:?}", compression);
+            }
+            assert_eq!(
+                decompress(compression, &compressed),
+                data.as_bytes(),
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn sniff_magic_numbers() {
+        for compression in Compression::ALL {
+            let compressed = compress(compression, b"{\"items\": []}", None);
+            assert_eq!(Compression::sniff(&compressed), compression);
+        }
+
+        assert_eq!(Compression::sniff(b""), Compression::None);
+        assert_eq!(Compression::sniff(&[0x1f]), Compression::None);
+        assert_eq!(Compression::sniff(b"BZ"), Compression::None);
+        assert_eq!(Compression::sniff(b"\xfd7zXZ"), Compression::None);
+    }
+
+    #[test]
+    fn concatenated_streams() {
+        for compression in Compression::ALL {
+            let mut data = compress(compression, b"{\"n\": 1}\n", None);
+            data.extend(compress(compression, b"{\"n\": 2}\n", None));
+
+            assert_eq!(
+                decompress(compression, &data),
+                b"{\"n\": 1}\n{\"n\": 2}\n",
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn levels() {
+        for compression in Compression::ALL {
+            let (min, max, default) = compression.levels();
+            assert_eq!(compression.check_level(None).unwrap(), default);
+
+            for level in [min, max] {
+                let compressed = compress(compression, b"abc", Some(level));
+                assert_eq!(decompress(compression, &compressed), b"abc");
+            }
+        }
+
+        assert!(Compression::Gzip.check_level(Some(10)).is_err());
+        assert!(Compression::Zstd.check_level(Some(0)).is_err());
+        assert!(Compression::Bzip2.encoder(vec![], Some(0)).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Keeping one record per DOI across overlapping snapshots, for inputs larger than memory.
+
+use std::path::Path;
+
+use crate::{
+    group::{newest, Grouper, Groups},
+    record::Record,
+};
+
+/// Collects records on disk, then yields the newest record for each DOI.
+///
+/// The newest record is the one with the latest Crossref `indexed` timestamp, falling back to `deposited`,
+/// or the latest DataCite `updated` timestamp. Where records have the same timestamp, or none, the one added last wins.
+/// Records without DOIs are all kept.
+pub struct Deduplicator {
+    grouper: Grouper,
+}
+
+impl Deduplicator {
+    /// Create a deduplicator with partition files in a new temporary directory inside `temp_dir`.
+    /// See [`Grouper::new`].
+    pub fn new(temp_dir: &Path, partitions: usize) -> anyhow::Result<Deduplicator> {
+        Ok(Deduplicator {
+            grouper: Grouper::new(temp_dir, partitions)?,
+        })
+    }
+
+    pub fn add(&mut self, record: &Record) -> anyhow::Result<()> {
+        self.grouper.add(record, 0)
+    }
+
+    /// Number of records added.
+    pub fn len(&self) -> usize {
+        self.grouper.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grouper.is_empty()
+    }
+
+    /// Finish adding records, and iterate over the newest record for each DOI.
+    pub fn finish(self) -> anyhow::Result<DedupedRecords> {
+        Ok(DedupedRecords {
+            groups: self.grouper.finish()?,
+            removed: 0,
+        })
+    }
+}
+
+/// Iterator over deduplicated records, from [`Deduplicator::finish`].
+pub struct DedupedRecords {
+    groups: Groups,
+    removed: usize,
+}
+
+impl DedupedRecords {
+    /// Number of records removed as duplicates so far.
+    pub fn removed(&self) -> usize {
+        self.removed
+    }
+}
+
+impl Iterator for DedupedRecords {
+    type Item = anyhow::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let group = match self.groups.next()? {
+            Ok(group) => group,
+            Err(err) => return Some(Err(err)),
+        };
+
+        self.removed += group.len() - 1;
+        let newest = newest(&group)?;
+        Some(self.groups.read(newest))
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Chain, Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::compression::{Compression, Decoder};
+
+/// Number of bytes needed to recognise any compression format's magic number.
+const MAGIC_SIZE: u64 = 6;
+
+/// Number of decompressed bytes examined to recognise the container.
+/// Enough for a tar header, or leading whitespace and the first key of a JSON object.
+const SNIFF_SIZE: u64 = 1024;
+
+/// Position of the "ustar" magic string in a POSIX tar header.
+const TAR_MAGIC_OFFSET: usize = 257;
+const TAR_MAGIC: &[u8] = b"ustar";
+
+/// How records are arranged within an input file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Container {
+    /// A JSON object with an "items" array, as in the Crossref public data file.
+    CrossrefJson,
+
+    /// A tar archive of `.jsonl` files, as in the DataCite public data file.
+    Tar,
+
+    /// JSON Lines, one record per line, as generated by this tool.
+    JsonLines,
+}
+
+impl Container {
+    /// Recognise the container from the first decompressed bytes of a file.
+    /// A JSON object with a top-level "items" key is a Crossref file, any other is taken to be the first of many JSON Lines.
+    pub fn sniff(prefix: &[u8]) -> Result<Container, SkipReason> {
+        if prefix.get(TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()) == Some(TAR_MAGIC) {
+            return Ok(Container::Tar);
+        }
+
+        let json = skip_whitespace(prefix);
+        if json.starts_with(b"{") {
+            if has_items_key(json) {
+                Ok(Container::CrossrefJson)
+            } else {
+                Ok(Container::JsonLines)
+            }
+        } else if json.is_empty() {
+            Err(SkipReason::Empty)
+        } else {
+            Err(SkipReason::Unrecognised)
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+/// Whether the JSON object at the start of `json` has an "items" key at the top level.
+/// Only the keys that start within `json` are found, so it's usually first.
+fn has_items_key(json: &[u8]) -> bool {
+    let mut depth = 0;
+    let mut expect_key = false;
+    let mut i = 0;
+
+    while i < json.len() {
+        match json[i] {
+            b'"' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < json.len() && json[end] != b'"' {
+                    // Skip escaped characters, including quotes.
+                    if json[end] == b'\\' {
+                        end += 1;
+                    }
+                    end += 1;
+                }
+
+                if end >= json.len() {
+                    return false;
+                }
+
+                if depth == 1 && expect_key {
+                    if &json[start..end] == b"items" {
+                        return true;
+                    }
+                    expect_key = false;
+                }
+
+                i = end;
+            }
+            b'{' | b'[' => {
+                depth += 1;
+                expect_key = depth == 1;
+            }
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return false;
+                }
+            }
+            b',' if depth == 1 => expect_key = true,
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    false
+}
+
+/// The container and compression of an input file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputFormat {
+    pub container: Container,
+    pub compression: Compression,
+}
+
+/// Decompressed content of a stream that has been sniffed, from the start.
+/// The bytes consumed while sniffing are replayed before the rest of the stream.
+pub type SniffedReader<R> = BufReader<Chain<Cursor<Vec<u8>>, Decoder<Chain<Cursor<Vec<u8>>, R>>>>;
+
+impl InputFormat {
+    /// Recognise a file's format from its content, regardless of its name.
+    /// Any of the container types can be uncompressed, or compressed with gzip, zstd, xz or bzip2.
+    pub fn detect(path: &Path) -> Result<InputFormat, SkipReason> {
+        InputFormat::sniff(File::open(path)?).map(|(format, _)| format)
+    }
+
+    /// Recognise a stream's format from its content, as [`InputFormat::detect`] does for files.
+    /// Also returns a reader for the decompressed content, as the stream can't be reopened.
+    pub fn sniff<R: Read>(mut reader: R) -> Result<(InputFormat, SniffedReader<R>), SkipReason> {
+        let mut magic = Vec::new();
+        (&mut reader).take(MAGIC_SIZE).read_to_end(&mut magic)?;
+        let compression = Compression::sniff(&magic);
+
+        let mut decoder = compression.decoder(Cursor::new(magic).chain(reader))?;
+        let mut prefix = Vec::new();
+        (&mut decoder).take(SNIFF_SIZE).read_to_end(&mut prefix)?;
+        let container = Container::sniff(&prefix)?;
+
+        let format = InputFormat {
+            container,
+            compression,
+        };
+        Ok((format, BufReader::new(Cursor::new(prefix).chain(decoder))))
+    }
+}
+
+/// Why a file wasn't read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// Couldn't be opened or decompressed.
+    Unreadable(String),
+
+    /// Empty, or only whitespace once decompressed.
+    Empty,
+
+    /// Neither a tar archive nor JSON.
+    Unrecognised,
+}
+
+impl From<io::Error> for SkipReason {
+    fn from(err: io::Error) -> Self {
+        SkipReason::Unreadable(err.to_string())
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Unreadable(err) => write!(f, "couldn't be read: {}", err),
+            SkipReason::Empty => write!(f, "empty"),
+            SkipReason::Unrecognised => write!(f, "not a tar archive or JSON"),
+        }
+    }
+}
+
+/// A file that will be read.
+#[derive(Clone, Debug)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub format: InputFormat,
+}
+
+/// A file that was found but won't be read.
+#[derive(Clone, Debug)]
+pub struct SkippedFile {
+    pub path: PathBuf,
+    pub reason: SkipReason,
+}
+
+/// All files found in an input path.
+#[derive(Clone, Debug, Default)]
+pub struct InputFiles {
+    /// Files that were recognised, in the order they'll be read.
+    pub files: Vec<InputFile>,
+
+    /// Files that weren't recognised, with the reason.
+    pub skipped: Vec<SkippedFile>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_key_at_top_level() {
+        assert!(has_items_key(br#"{"items": []}"#));
+        assert!(has_items_key(
+            br#"{"status": "ok", "items": [{"DOI": "10.1/a"}]}"#
+        ));
+        assert!(has_items_key(
+            br#"{"a": {"b": 1}, "c": [1, 2], "items": []}"#
+        ));
+    }
+
+    #[test]
+    fn items_key_nested_or_as_value() {
+        assert!(!has_items_key(br#"{"message": {"items": []}}"#));
+        assert!(!has_items_key(br#"{"title": "items"}"#));
+        assert!(!has_items_key(br#"{"list": ["items"]}"#));
+        assert!(!has_items_key(br#"{"DOI": "10.1/a"} {"items": []}"#));
+    }
+
+    #[test]
+    fn items_key_after_escaped_quote() {
+        assert!(has_items_key(
+            br#"{"title": "a \"quoted\" word", "items": []}"#
+        ));
+        assert!(!has_items_key(br#"{"title": "\", \"items\": "}"#));
+    }
+
+    #[test]
+    fn items_key_cut_off() {
+        assert!(!has_items_key(br#"{"title": "a long "#));
+        assert!(!has_items_key(br#"{"ite"#));
+    }
+
+    #[test]
+    fn sniff_containers() {
+        assert_eq!(
+            Container::sniff(b"  \n{\"items\": []}"),
+            Ok(Container::CrossrefJson)
+        );
+        assert_eq!(
+            Container::sniff(b"{\"doi\": \"10.1/a\"}\n{\"doi\": \"10.1/b\"}\n"),
+            Ok(Container::JsonLines)
+        );
+
+        let mut tar = vec![0; 512];
+        tar[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + TAR_MAGIC.len()].copy_from_slice(TAR_MAGIC);
+        assert_eq!(Container::sniff(&tar), Ok(Container::Tar));
+    }
+
+    #[test]
+    fn sniff_rejects_empty_and_other_content() {
+        assert_eq!(Container::sniff(b""), Err(SkipReason::Empty));
+        assert_eq!(Container::sniff(b" \n\t"), Err(SkipReason::Empty));
+        assert_eq!(
+            Container::sniff(b"doi,title\n"),
+            Err(SkipReason::Unrecognised)
+        );
+        assert_eq!(Container::sniff(b"[1, 2]"), Err(SkipReason::Unrecognised));
+    }
+}
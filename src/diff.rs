@@ -0,0 +1,270 @@
+//! Comparing two sets of snapshots by DOI, to find records added, removed and modified.
+
+use std::{collections::BTreeMap, fmt};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::group::{canonical_hash, newest, Entry, Groups};
+
+/// Tag for records from the old input.
+pub const OLD: u32 = 0;
+
+/// Tag for records from the new input.
+pub const NEW: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added => write!(f, "added"),
+            Change::Removed => write!(f, "removed"),
+            Change::Modified => write!(f, "modified"),
+        }
+    }
+}
+
+/// A DOI whose record differs between the old and new inputs.
+#[derive(Clone, Debug, Serialize)]
+pub struct DiffEntry {
+    /// Normalized DOI.
+    pub doi: String,
+
+    pub change: Change,
+
+    /// For modified records, the top-level keys whose values were added, removed or changed, if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changed_keys: Option<Vec<String>>,
+}
+
+/// Counts of changes.
+#[derive(Debug, Default)]
+pub struct DiffSummary {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+    pub unchanged: usize,
+
+    /// Records without DOIs, which can't be compared.
+    pub without_doi: usize,
+
+    /// Number of modified records in which each top-level key changed, if requested.
+    pub changed_keys: BTreeMap<String, usize>,
+}
+
+impl DiffSummary {
+    pub fn new() -> DiffSummary {
+        DiffSummary::default()
+    }
+
+    /// Print the summary to STDERR, so that it doesn't mix with diff entries on STDOUT.
+    pub fn print(&self) {
+        eprintln!("Diff:");
+        eprintln!("DOIs added: {}", self.added);
+        eprintln!("DOIs removed: {}", self.removed);
+        eprintln!("DOIs modified: {}", self.modified);
+        eprintln!("DOIs unchanged: {}", self.unchanged);
+        eprintln!("Records without DOIs: {}", self.without_doi);
+
+        if !self.changed_keys.is_empty() {
+            eprintln!("Changed keys:");
+            for (key, count) in self.changed_keys.iter() {
+                eprintln!("  {}: {}", key, count);
+            }
+        }
+    }
+}
+
+/// Compare the old and new records for one DOI, from a group of records tagged [`OLD`] or [`NEW`].
+/// Where an input has more than one record for the DOI, the newest is used.
+/// Returns None if the DOI is unchanged.
+/// If `changed_keys` is true, modified records are read to find which top-level keys changed.
+pub fn compare(
+    groups: &mut Groups,
+    group: &[Entry],
+    changed_keys: bool,
+    summary: &mut DiffSummary,
+) -> anyhow::Result<Option<DiffEntry>> {
+    let Some(doi) = group.first().and_then(|entry| entry.doi.clone()) else {
+        summary.without_doi += group.len();
+        return Ok(None);
+    };
+
+    let old = newest(group.iter().filter(|entry| entry.tag == OLD));
+    let new = newest(group.iter().filter(|entry| entry.tag == NEW));
+
+    let entry = match (old, new) {
+        (None, None) => None,
+        (None, Some(_)) => {
+            summary.added += 1;
+            Some(DiffEntry {
+                doi,
+                change: Change::Added,
+                changed_keys: None,
+            })
+        }
+        (Some(_), None) => {
+            summary.removed += 1;
+            Some(DiffEntry {
+                doi,
+                change: Change::Removed,
+                changed_keys: None,
+            })
+        }
+        (Some(old), Some(new)) if old.hash == new.hash => {
+            summary.unchanged += 1;
+            None
+        }
+        (Some(old), Some(new)) => {
+            summary.modified += 1;
+
+            let keys = if changed_keys {
+                let old = groups.read(old)?;
+                let new = groups.read(new)?;
+                let keys = diff_keys(&old.value, &new.value);
+                for key in keys.iter() {
+                    *summary.changed_keys.entry(key.clone()).or_insert(0) += 1;
+                }
+                Some(keys)
+            } else {
+                None
+            };
+
+            Some(DiffEntry {
+                doi,
+                change: Change::Modified,
+                changed_keys: keys,
+            })
+        }
+    };
+
+    Ok(entry)
+}
+
+/// Top-level keys whose values differ between two records, in sorted order.
+/// If either isn't a JSON object then there are no keys to compare.
+fn diff_keys(old: &Value, new: &Value) -> Vec<String> {
+    let (Value::Object(old), Value::Object(new)) = (old, new) else {
+        return vec![];
+    };
+
+    let mut keys: Vec<String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| match (old.get(*key), new.get(*key)) {
+            (Some(old), Some(new)) => canonical_hash(old) != canonical_hash(new),
+            _ => true,
+        })
+        .cloned()
+        .collect();
+
+    keys.sort_unstable();
+    keys.dedup();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::{group::Grouper, record::Record};
+
+    /// Compare old and new records, returning the changes sorted by DOI, and the summary.
+    fn diff(old: Vec<Value>, new: Vec<Value>) -> (Vec<DiffEntry>, DiffSummary) {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Arc<Path> = Arc::from(Path::new("input.jsonl"));
+        let mut grouper = Grouper::new(dir.path(), 2).unwrap();
+        for value in old {
+            grouper
+                .add(&Record::new(source.clone(), value), OLD)
+                .unwrap();
+        }
+        for value in new {
+            grouper
+                .add(&Record::new(source.clone(), value), NEW)
+                .unwrap();
+        }
+
+        let mut summary = DiffSummary::new();
+        let mut groups = grouper.finish().unwrap();
+        let mut entries = vec![];
+        while let Some(group) = groups.next() {
+            let group = group.unwrap();
+            if let Some(entry) = compare(&mut groups, &group, true, &mut summary).unwrap() {
+                entries.push(entry);
+            }
+        }
+        entries.sort_by(|a, b| a.doi.cmp(&b.doi));
+        (entries, summary)
+    }
+
+    #[test]
+    fn added_removed_modified_unchanged() {
+        let (entries, summary) = diff(
+            vec![
+                json!({"DOI": "10.1/removed"}),
+                json!({"DOI": "10.1/modified", "title": "Old", "page": "1"}),
+                json!({"DOI": "10.1/same", "a": 1, "b": 2}),
+                json!({"title": "no DOI"}),
+            ],
+            vec![
+                json!({"DOI": "10.1/added"}),
+                json!({"DOI": "10.1/MODIFIED", "title": "New", "volume": "2"}),
+                json!({"b": 2, "a": 1, "DOI": "10.1/same"}),
+            ],
+        );
+
+        let changes: Vec<(&str, Change)> = entries
+            .iter()
+            .map(|entry| (entry.doi.as_str(), entry.change))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("10.1/added", Change::Added),
+                ("10.1/modified", Change::Modified),
+                ("10.1/removed", Change::Removed),
+            ]
+        );
+
+        // The DOI was written differently, so it changed too.
+        assert_eq!(
+            entries[1].changed_keys,
+            Some(vec![
+                String::from("DOI"),
+                String::from("page"),
+                String::from("title"),
+                String::from("volume"),
+            ])
+        );
+
+        assert_eq!(summary.unchanged, 1);
+        assert_eq!(summary.without_doi, 1);
+        assert_eq!(summary.changed_keys.get("title"), Some(&1));
+    }
+
+    #[test]
+    fn newest_record_compared() {
+        let (entries, summary) = diff(
+            vec![
+                json!({"DOI": "10.1/a", "indexed": {"date-time": "2024-01-02T00:00:00Z"}, "v": 2}),
+                json!({"DOI": "10.1/a", "indexed": {"date-time": "2024-01-01T00:00:00Z"}, "v": 1}),
+            ],
+            vec![
+                json!({"DOI": "10.1/a", "indexed": {"date-time": "2024-01-02T00:00:00Z"}, "v": 2}),
+            ],
+        );
+        assert!(entries.is_empty());
+        assert_eq!(summary.unchanged, 1);
+    }
+}
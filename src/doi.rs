@@ -0,0 +1,176 @@
+//! Normalizing and validating DOIs, so that different ways of writing the same DOI compare equal.
+
+use std::fmt;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// Prefixes that are sometimes written before a DOI, lowercase.
+const DOI_PREFIXES: &[&str] = &[
+    "https://doi.org/",
+    "http://doi.org/",
+    "https://dx.doi.org/",
+    "http://dx.doi.org/",
+    "doi:",
+];
+
+/// Normalize a DOI for comparison: trim whitespace, strip any resolver URL or `doi:` prefix,
+/// lowercase, and convert to Unicode NFC.
+/// DOIs are case-insensitive, so e.g. `https://doi.org/10.5555/ABC` becomes `10.5555/abc`.
+pub fn normalize(doi: &str) -> String {
+    let mut doi = doi.trim();
+
+    for prefix in DOI_PREFIXES {
+        if doi
+            .get(..prefix.len())
+            .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        {
+            doi = doi[prefix.len()..].trim_start();
+            break;
+        }
+    }
+
+    // Lowercasing can produce decomposed characters, so compose afterwards.
+    doi.to_lowercase().nfc().collect()
+}
+
+/// The prefix of a DOI, before the first slash, e.g. `10.5555`. None if there's no slash.
+pub fn prefix(doi: &str) -> Option<&str> {
+    doi.split_once('/').map(|(prefix, _)| prefix)
+}
+
+/// Something wrong with a record's DOI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DoiProblem {
+    /// The record has no DOI.
+    Missing,
+
+    /// Not of the form `10.<registrant>/<suffix>`, e.g. a URL, or surrounded by whitespace.
+    Malformed,
+
+    /// Contains characters outside ASCII. These are allowed, but often come from copy-and-paste errors.
+    NonAscii,
+
+    /// Contains control characters, which are never valid.
+    ControlCharacters,
+}
+
+impl DoiProblem {
+    pub const ALL: [DoiProblem; 4] = [
+        DoiProblem::Missing,
+        DoiProblem::Malformed,
+        DoiProblem::NonAscii,
+        DoiProblem::ControlCharacters,
+    ];
+}
+
+impl fmt::Display for DoiProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DoiProblem::Missing => write!(f, "missing"),
+            DoiProblem::Malformed => write!(f, "malformed"),
+            DoiProblem::NonAscii => write!(f, "non-ASCII"),
+            DoiProblem::ControlCharacters => write!(f, "control characters"),
+        }
+    }
+}
+
+/// Check a DOI as found in a record, before normalization. Empty if there's nothing wrong.
+pub fn problems(doi: Option<&str>) -> Vec<DoiProblem> {
+    let Some(doi) = doi else {
+        return vec![DoiProblem::Missing];
+    };
+
+    let mut problems = vec![];
+
+    if !is_well_formed(doi) {
+        problems.push(DoiProblem::Malformed);
+    }
+
+    if !doi.is_ascii() {
+        problems.push(DoiProblem::NonAscii);
+    }
+
+    if doi.chars().any(char::is_control) {
+        problems.push(DoiProblem::ControlCharacters);
+    }
+
+    problems
+}
+
+/// Whether the DOI has the form `10.<registrant>/<suffix>`.
+/// The registrant code is digits, possibly with dot-separated subdivisions.
+/// The suffix is anything without whitespace.
+fn is_well_formed(doi: &str) -> bool {
+    let Some((prefix, suffix)) = doi.split_once('/') else {
+        return false;
+    };
+
+    let registrant_ok = prefix.strip_prefix("10.").is_some_and(|registrant| {
+        registrant
+            .split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+    });
+
+    registrant_ok && !suffix.is_empty() && !suffix.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_strips_prefixes() {
+        assert_eq!(normalize("https://doi.org/10.5555/ABC"), "10.5555/abc");
+        assert_eq!(normalize("HTTP://DX.DOI.ORG/10.5555/abc"), "10.5555/abc");
+        assert_eq!(normalize("doi: 10.5555/abc"), "10.5555/abc");
+        assert_eq!(normalize("  10.5555/abc\n"), "10.5555/abc");
+    }
+
+    #[test]
+    fn normalize_only_strips_one_prefix_at_the_start() {
+        assert_eq!(
+            normalize("10.5555/https://doi.org/x"),
+            "10.5555/https://doi.org/x"
+        );
+        assert_eq!(normalize("doi:doi:10.5555/x"), "doi:10.5555/x");
+    }
+
+    #[test]
+    fn normalize_composes_unicode() {
+        // "e" followed by a combining acute accent, and the precomposed "é".
+        assert_eq!(normalize("10.5555/E\u{301}"), "10.5555/\u{e9}");
+        assert_eq!(normalize("10.5555/\u{c9}"), "10.5555/\u{e9}");
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(prefix("10.5555/abc/def"), Some("10.5555"));
+        assert_eq!(prefix("10.5555"), None);
+    }
+
+    #[test]
+    fn problems_found() {
+        assert!(problems(Some("10.5555/abc")).is_empty());
+        assert!(problems(Some("10.1000.10/abc")).is_empty());
+        assert_eq!(problems(None), vec![DoiProblem::Missing]);
+        assert_eq!(
+            problems(Some("https://doi.org/10.5555/abc")),
+            vec![DoiProblem::Malformed]
+        );
+        assert_eq!(problems(Some(" 10.5555/abc")), vec![DoiProblem::Malformed]);
+        assert_eq!(problems(Some("10./abc")), vec![DoiProblem::Malformed]);
+        assert_eq!(problems(Some("10.5555/")), vec![DoiProblem::Malformed]);
+        assert_eq!(
+            problems(Some("10.5555/caf\u{e9}")),
+            vec![DoiProblem::NonAscii]
+        );
+        assert_eq!(
+            problems(Some("10.5555/a\u{0}b")),
+            vec![DoiProblem::ControlCharacters]
+        );
+        assert_eq!(
+            problems(Some("10.5555/a\nb")),
+            vec![DoiProblem::Malformed, DoiProblem::ControlCharacters]
+        );
+    }
+}
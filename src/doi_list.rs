@@ -0,0 +1,170 @@
+//! Sets of DOIs loaded from list files, for including or excluding records.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::doi::normalize;
+
+/// A set of DOIs read from a file, one per line.
+/// Blank lines and lines starting with `#` are ignored.
+///
+/// To keep memory use low for lists of millions of DOIs, only a 64 bit hash of each normalized DOI is held.
+/// The chance of a false match is negligible at that size.
+/// The set records which DOIs were looked up, so that those not found can be reported.
+pub struct DoiSet {
+    path: PathBuf,
+
+    /// Sorted and deduplicated.
+    hashes: Vec<u64>,
+
+    /// Whether each hash has been found, by index.
+    found: Vec<AtomicBool>,
+}
+
+impl DoiSet {
+    pub fn from_file(path: &Path) -> anyhow::Result<DoiSet> {
+        let mut hashes = Vec::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            if let Some(hash) = hash_entry(&line?) {
+                hashes.push(hash);
+            }
+        }
+
+        hashes.sort_unstable();
+        hashes.dedup();
+        let found = hashes.iter().map(|_| AtomicBool::new(false)).collect();
+
+        Ok(DoiSet {
+            path: path.to_path_buf(),
+            hashes,
+            found,
+        })
+    }
+
+    /// Number of distinct DOIs.
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Whether the DOI is in the set, after normalizing. Records that it was found.
+    pub fn check(&self, doi: &str) -> bool {
+        match self.hashes.binary_search(&hash_doi(&normalize(doi))) {
+            Ok(i) => {
+                self.found[i].store(true, Ordering::Relaxed);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Number of distinct DOIs that haven't been found.
+    pub fn not_found_count(&self) -> usize {
+        self.found
+            .iter()
+            .filter(|found| !found.load(Ordering::Relaxed))
+            .count()
+    }
+
+    /// Write the entries in the list file that haven't been found, one per line, as they appear in the file.
+    /// The file is read again, so that the set doesn't have to hold them.
+    pub fn write_not_found(&self, out: &mut impl Write) -> anyhow::Result<()> {
+        // So that duplicates in the list are only reported once.
+        let mut reported = vec![false; self.hashes.len()];
+
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            let Some(hash) = hash_entry(&line) else {
+                continue;
+            };
+
+            if let Ok(i) = self.hashes.binary_search(&hash) {
+                if !self.found[i].load(Ordering::Relaxed) && !reported[i] {
+                    reported[i] = true;
+                    writeln!(out, "{}", line.trim())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for DoiSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DoiSet")
+            .field("path", &self.path)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Hash a line of a list file. None if it isn't a DOI.
+fn hash_entry(line: &str) -> Option<u64> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let doi = normalize(line);
+    (!doi.is_empty()).then(|| hash_doi(&doi))
+}
+
+fn hash_doi(normalized_doi: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    normalized_doi.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn list(content: &str) -> (tempfile::TempDir, DoiSet) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dois.txt");
+        fs::write(&path, content).unwrap();
+        let set = DoiSet::from_file(&path).unwrap();
+        (dir, set)
+    }
+
+    #[test]
+    fn normalized_on_load_and_check() {
+        let (_dir, set) = list(
+            "# Comment\n\n10.5555/ABC\n  https://doi.org/10.5555/def  \n10.5555/abc\n   \n#10.5555/ghi\n",
+        );
+        assert_eq!(set.len(), 2);
+
+        assert!(set.check("10.5555/abc"));
+        assert!(set.check("doi:10.5555/DEF"));
+        assert!(!set.check("10.5555/ghi"));
+        assert!(!set.check("# Comment"));
+    }
+
+    #[test]
+    fn not_found_reported_once() {
+        let (_dir, set) =
+            list("10.5555/a\n10.5555/B\n# 10.5555/c\nhttps://doi.org/10.5555/b\n10.5555/d\n");
+        assert_eq!(set.not_found_count(), 3);
+
+        set.check("10.5555/D");
+        assert_eq!(set.not_found_count(), 2);
+
+        let mut out = vec![];
+        set.write_not_found(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "10.5555/a\n10.5555/B\n");
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    fmt,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use serde_json::json;
+
+/// What to do when part of an input can't be read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Report the error, skip the record (or the rest of the file) and carry on.
+    #[default]
+    Skip,
+
+    /// Report the error and stop reading.
+    Fail,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "fail" => Ok(ErrorPolicy::Fail),
+            _ => Err(anyhow::format_err!(
+                "Unrecognised error policy {:?}, expected \"skip\" or \"fail\"",
+                s
+            )),
+        }
+    }
+}
+
+/// A problem reading part of an input file.
+#[derive(Debug, Clone)]
+pub struct ReadError {
+    /// Input file.
+    pub file: PathBuf,
+
+    /// Entry within a tar archive, if applicable.
+    pub entry: Option<String>,
+
+    /// Line number, counting from 1, within the file or tar entry. None if the error affects the whole file.
+    pub line: Option<usize>,
+
+    pub error: String,
+
+    /// Whether only this record was lost, rather than the rest of the file.
+    pub record_only: bool,
+}
+
+impl ReadError {
+    /// An error affecting the remainder of a file.
+    pub(crate) fn file(file: &Path, error: &anyhow::Error) -> ReadError {
+        // Parse errors in a streamed file can at least say where they happened.
+        let line = error
+            .downcast_ref::<serde_json::Error>()
+            .map(|err| err.line())
+            .filter(|line| *line > 0);
+
+        ReadError {
+            file: file.to_path_buf(),
+            entry: None,
+            line,
+            error: format!("{:#}", error),
+            record_only: false,
+        }
+    }
+
+    /// An error affecting a single line.
+    pub(crate) fn line(
+        file: &Path,
+        entry: Option<&Path>,
+        line: usize,
+        error: &dyn fmt::Display,
+    ) -> ReadError {
+        ReadError {
+            file: file.to_path_buf(),
+            entry: entry.map(|entry| entry.to_string_lossy().to_string()),
+            line: Some(line),
+            error: error.to_string(),
+            record_only: true,
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.file)?;
+        if let Some(ref entry) = self.entry {
+            write!(f, " entry {:?}", entry)?;
+        }
+        if let Some(line) = self.line {
+            write!(f, " line {}", line)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// The consumer of records has gone away, so there's no point in reading any more.
+/// This isn't a problem with the input, so it isn't reported.
+#[derive(Debug)]
+pub(crate) struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Record channel closed")
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+/// Collects errors from all reader threads.
+/// Counts them, optionally logs them to a JSON Lines file, and applies the error policy.
+///
+/// One log can be shared by several readers with [`crate::SnapshotReader::errors`], e.g. when a command
+/// reads more than one input, or reads the same input more than once.
+#[derive(Debug)]
+pub struct ErrorLog {
+    policy: ErrorPolicy,
+    verbose: bool,
+    records_skipped: AtomicUsize,
+    files_abandoned: AtomicUsize,
+    log: Option<Mutex<BufWriter<File>>>,
+
+    /// Hashes of errors already reported, so that reading an input again doesn't report them twice.
+    reported: Mutex<HashSet<u64>>,
+}
+
+impl ErrorLog {
+    /// Create a log, replacing `log_file` if it exists.
+    pub fn new(
+        policy: ErrorPolicy,
+        log_file: Option<&Path>,
+        verbose: bool,
+    ) -> anyhow::Result<ErrorLog> {
+        let log = match log_file {
+            Some(log_file) => Some(Mutex::new(BufWriter::new(File::create(log_file)?))),
+            None => None,
+        };
+
+        Ok(ErrorLog {
+            policy,
+            verbose,
+            records_skipped: AtomicUsize::new(0),
+            files_abandoned: AtomicUsize::new(0),
+            log,
+            reported: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Report an error, either from reading or from later processing of a record.
+    /// An error already reported is only counted and logged once.
+    /// Under the `Fail` policy this returns the error, so the reader can stop.
+    pub fn report(&self, error: ReadError) -> anyhow::Result<()> {
+        if self.is_new(&error)? {
+            self.record(&error)?;
+        }
+
+        match self.policy {
+            ErrorPolicy::Skip => Ok(()),
+            ErrorPolicy::Fail => Err(error.into()),
+        }
+    }
+
+    /// Whether the error hasn't been reported before. Remembers that it has now.
+    fn is_new(&self, error: &ReadError) -> anyhow::Result<bool> {
+        let mut hasher = DefaultHasher::new();
+        (&error.file, &error.entry, error.line, &error.error).hash(&mut hasher);
+
+        Ok(self
+            .reported
+            .lock()
+            .map_err(|_| anyhow::format_err!("Error log poisoned"))?
+            .insert(hasher.finish()))
+    }
+
+    /// Count the error, and write it to STDERR and the log file if enabled.
+    fn record(&self, error: &ReadError) -> anyhow::Result<()> {
+        if error.record_only {
+            self.records_skipped.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.files_abandoned.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if self.verbose {
+            eprintln!("Read error: {}", error);
+        }
+
+        if let Some(ref log) = self.log {
+            let entry = json!({
+                "file": error.file.to_string_lossy(),
+                "entry": error.entry,
+                "line": error.line,
+                "error": error.error,
+                "record_only": error.record_only,
+            });
+
+            let mut log = log
+                .lock()
+                .map_err(|_| anyhow::format_err!("Error log poisoned"))?;
+            serde_json::to_writer(&mut *log, &entry)?;
+            log.write_all(b"\n")?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of records that couldn't be parsed.
+    pub fn records_skipped(&self) -> usize {
+        self.records_skipped.load(Ordering::Relaxed)
+    }
+
+    /// Number of files that couldn't be read to the end.
+    pub fn files_abandoned(&self) -> usize {
+        self.files_abandoned.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn flush(&self) -> anyhow::Result<()> {
+        if let Some(ref log) = self.log {
+            log.lock()
+                .map_err(|_| anyhow::format_err!("Error log poisoned"))?
+                .flush()?;
+        }
+        Ok(())
+    }
+}
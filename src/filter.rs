@@ -0,0 +1,357 @@
+//! Selecting a subset of records by DOI prefix, agency, type and date.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use serde_json::Value;
+
+use crate::{
+    doi::{self, normalize},
+    doi_list::DoiSet,
+    metadata::{get_agency, get_doi_from_record, Agency},
+};
+
+/// Criteria that records must meet to be read.
+/// A record must meet all of the criteria given. Criteria that are empty or None match everything.
+#[derive(Clone, Debug, Default)]
+pub struct RecordFilter {
+    /// Matches records with DOIs in the set.
+    pub dois: Option<Arc<DoiSet>>,
+
+    /// Matches records with DOIs not in the set, including records without DOIs.
+    pub exclude_dois: Option<Arc<DoiSet>>,
+
+    /// DOI prefixes, e.g. "10.5281". Matches records with any of them.
+    pub prefixes: Vec<String>,
+
+    /// Matches records from this agency.
+    pub agency: Option<Agency>,
+
+    /// Crossref type (e.g. "journal-article") or DataCite resourceTypeGeneral (e.g. "Dataset").
+    /// Compared case-insensitively. Matches records with any of them.
+    pub types: Vec<String>,
+
+    /// Crossref "published" date, or DataCite "publicationYear".
+    pub published: DateRange,
+
+    /// Crossref "deposited" date, or DataCite "updated" date.
+    pub updated: DateRange,
+}
+
+impl RecordFilter {
+    /// True if there are no criteria, so every record matches.
+    pub fn is_empty(&self) -> bool {
+        self.dois.is_none()
+            && self.exclude_dois.is_none()
+            && self.prefixes.is_empty()
+            && self.agency.is_none()
+            && self.types.is_empty()
+            && self.published.is_empty()
+            && self.updated.is_empty()
+    }
+
+    /// Whether the record meets all of the criteria.
+    pub fn matches(&self, record: &Value) -> bool {
+        let doi = get_doi_from_record(record);
+
+        // DOI lists are checked first, so that every DOI that's present is recorded as found,
+        // even if the record doesn't meet other criteria.
+        let in_dois = self
+            .dois
+            .as_ref()
+            .map(|dois| doi.as_deref().is_some_and(|doi| dois.check(doi)));
+        let in_exclude_dois = self
+            .exclude_dois
+            .as_ref()
+            .map(|dois| doi.as_deref().is_some_and(|doi| dois.check(doi)));
+
+        if in_dois == Some(false) || in_exclude_dois == Some(true) {
+            return false;
+        }
+
+        if !self.prefixes.is_empty() {
+            let normalized = doi.as_deref().map(normalize);
+            let Some(prefix) = normalized.as_deref().and_then(doi::prefix) else {
+                return false;
+            };
+
+            if !self
+                .prefixes
+                .iter()
+                .any(|wanted| normalize(wanted.trim_end_matches('/')) == prefix)
+            {
+                return false;
+            }
+        }
+
+        if self.agency.is_some() && get_agency(record) != self.agency {
+            return false;
+        }
+
+        if !self.types.is_empty() {
+            let Some(resource_type) = get_resource_type(record) else {
+                return false;
+            };
+
+            if !self
+                .types
+                .iter()
+                .any(|wanted| wanted.eq_ignore_ascii_case(resource_type))
+            {
+                return false;
+            }
+        }
+
+        if !self.published.is_empty() && !self.published.contains(get_published(record)) {
+            return false;
+        }
+
+        if !self.updated.is_empty() && !self.updated.contains(get_updated(record)) {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// An inclusive range of dates, either end of which may be open.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub from: Option<FilterDate>,
+    pub until: Option<FilterDate>,
+}
+
+impl DateRange {
+    pub fn is_empty(&self) -> bool {
+        self.from.is_none() && self.until.is_none()
+    }
+
+    /// Whether the date falls in the range. A record without a date doesn't.
+    /// Dates are compared at the coarser of the two precisions, so that e.g. a record published in 2020
+    /// is within a range from 2020-06-01.
+    fn contains(&self, date: Option<FilterDate>) -> bool {
+        let Some(date) = date else {
+            return false;
+        };
+
+        let from_ok = self
+            .from
+            .as_ref()
+            .is_none_or(|from| date.compare(from).is_ge());
+        let until_ok = self
+            .until
+            .as_ref()
+            .is_none_or(|until| date.compare(until).is_le());
+
+        from_ok && until_ok
+    }
+}
+
+/// A date with a year, and optionally a month and day, such as "2020", "2020-05" or "2020-05-01".
+/// Ordered by year, then month, then day, with a less precise date before a more precise one in the same period.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FilterDate {
+    parts: Vec<u32>,
+}
+
+impl FilterDate {
+    /// Compare only as many parts as both dates have.
+    fn compare(&self, other: &FilterDate) -> std::cmp::Ordering {
+        let len = self.parts.len().min(other.parts.len());
+        self.parts[..len].cmp(&other.parts[..len])
+    }
+
+    /// Take the date from the start of an ISO 8601 date or date-time. None if it's not one.
+    fn from_iso8601(value: &str) -> Option<FilterDate> {
+        let date = value.split('T').next()?;
+        date.parse().ok()
+    }
+
+    /// From Crossref "date-parts", e.g. `[[2020, 5, 1]]`.
+    fn from_date_parts(value: &Value) -> Option<FilterDate> {
+        let parts: Vec<u32> = value
+            .get("date-parts")?
+            .get(0)?
+            .as_array()?
+            .iter()
+            .map_while(|part| part.as_u64().and_then(|part| u32::try_from(part).ok()))
+            .take(3)
+            .collect();
+
+        (!parts.is_empty()).then_some(FilterDate { parts })
+    }
+}
+
+impl FromStr for FilterDate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split('-')
+            .map(|part| part.parse::<u32>())
+            .collect::<Result<Vec<u32>, _>>()
+            .ok()
+            .filter(|parts| (1..=3).contains(&parts.len()))
+            .ok_or_else(|| {
+                anyhow::format_err!(
+                    "Unrecognised date {:?}, expected YYYY, YYYY-MM or YYYY-MM-DD",
+                    s
+                )
+            })?;
+
+        Ok(FilterDate { parts })
+    }
+}
+
+impl fmt::Display for FilterDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, part) in self.parts.iter().enumerate() {
+            if i == 0 {
+                write!(f, "{:04}", part)?;
+            } else {
+                write!(f, "-{:02}", part)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// DataCite metadata, which may be under "attributes" when it comes from the REST API.
+pub(crate) fn datacite_attributes(record: &Value) -> &Value {
+    record.get("attributes").unwrap_or(record)
+}
+
+/// Crossref type or DataCite resourceTypeGeneral.
+fn get_resource_type(record: &Value) -> Option<&str> {
+    match get_agency(record)? {
+        Agency::Crossref => record.get("type")?.as_str(),
+        Agency::DataCite => datacite_attributes(record)
+            .get("types")?
+            .get("resourceTypeGeneral")?
+            .as_str(),
+    }
+}
+
+/// Crossref "published" date, falling back to "issued", or DataCite "publicationYear".
+fn get_published(record: &Value) -> Option<FilterDate> {
+    match get_agency(record)? {
+        Agency::Crossref => record
+            .get("published")
+            .and_then(FilterDate::from_date_parts)
+            .or_else(|| record.get("issued").and_then(FilterDate::from_date_parts)),
+        Agency::DataCite => {
+            let year = datacite_attributes(record).get("publicationYear")?;
+            match year {
+                Value::String(year) => year.parse().ok(),
+                year => year
+                    .as_u64()
+                    .and_then(|year| u32::try_from(year).ok())
+                    .map(|year| FilterDate { parts: vec![year] }),
+            }
+        }
+    }
+}
+
+/// Crossref "deposited" date, or DataCite "updated" date.
+fn get_updated(record: &Value) -> Option<FilterDate> {
+    match get_agency(record)? {
+        Agency::Crossref => {
+            let deposited = record.get("deposited")?;
+            deposited
+                .get("date-time")
+                .and_then(|date_time| date_time.as_str())
+                .and_then(FilterDate::from_iso8601)
+                .or_else(|| FilterDate::from_date_parts(deposited))
+        }
+        Agency::DataCite => datacite_attributes(record)
+            .get("updated")?
+            .as_str()
+            .and_then(FilterDate::from_iso8601),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn date(s: &str) -> FilterDate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_dates() {
+        assert_eq!(date("2020").parts, vec![2020]);
+        assert_eq!(date("2020-05").parts, vec![2020, 5]);
+        assert_eq!(date("2020-05-01").parts, vec![2020, 5, 1]);
+        assert_eq!(date("2020-5-1").to_string(), "2020-05-01");
+    }
+
+    #[test]
+    fn reject_bad_dates() {
+        for s in ["", "20x0", "2020-", "2020-05-01-02", "2020/05/01", "-2020"] {
+            assert!(s.parse::<FilterDate>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn iso8601_dates() {
+        assert_eq!(
+            FilterDate::from_iso8601("2024-01-31T12:00:00Z"),
+            Some(date("2024-01-31"))
+        );
+        assert_eq!(
+            FilterDate::from_iso8601("2024-01-31"),
+            Some(date("2024-01-31"))
+        );
+        assert_eq!(FilterDate::from_iso8601("yesterday"), None);
+    }
+
+    #[test]
+    fn date_parts() {
+        assert_eq!(
+            FilterDate::from_date_parts(&json!({"date-parts": [[2020, 5]]})),
+            Some(date("2020-05"))
+        );
+        assert_eq!(
+            FilterDate::from_date_parts(&json!({"date-parts": [[2020, 5, 1, 9]]})),
+            Some(date("2020-05-01"))
+        );
+        assert_eq!(
+            FilterDate::from_date_parts(&json!({"date-parts": [[null]]})),
+            None
+        );
+    }
+
+    #[test]
+    fn ranges_compare_at_coarser_precision() {
+        let range = DateRange {
+            from: Some(date("2020-06-01")),
+            until: Some(date("2021-02")),
+        };
+
+        assert!(range.contains(Some(date("2020"))));
+        assert!(range.contains(Some(date("2020-06-01"))));
+        assert!(range.contains(Some(date("2021-02-28"))));
+        assert!(!range.contains(Some(date("2020-05-31"))));
+        assert!(!range.contains(Some(date("2021-03"))));
+        assert!(!range.contains(None));
+    }
+
+    #[test]
+    fn published_and_updated() {
+        let crossref = json!({
+            "DOI": "10.1/a",
+            "issued": {"date-parts": [[2019]]},
+            "deposited": {"date-time": "2023-04-05T06:07:08Z"},
+        });
+        assert_eq!(get_published(&crossref), Some(date("2019")));
+        assert_eq!(get_updated(&crossref), Some(date("2023-04-05")));
+
+        let datacite = json!({
+            "attributes": {"doi": "10.1/b", "publicationYear": "2018", "updated": "2022-01-02T00:00:00Z"}
+        });
+        assert_eq!(get_published(&datacite), Some(date("2018")));
+        assert_eq!(get_updated(&datacite), Some(date("2022-01-02")));
+    }
+}
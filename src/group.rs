@@ -0,0 +1,570 @@
+//! Grouping records by DOI, for inputs larger than memory.
+//!
+//! Records are partitioned by a hash of their normalized DOI into temporary files on disk.
+//! Each partition is then grouped on its own, so only one partition's index is held in memory at a time.
+//! This is the basis of deduplication, diffs, histories and updates.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tempfile::TempDir;
+
+use crate::{
+    filter::datacite_attributes,
+    metadata::{get_agency, Agency},
+    record::Record,
+};
+
+/// A record stored by a [`Grouper`]. The record itself is read with [`Groups::read`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// Normalized DOI.
+    pub doi: Option<String>,
+
+    /// When the record was last updated, as a string that sorts in time order. See [`updated_key`].
+    pub updated: Option<String>,
+
+    /// Hash of the record's content, from [`canonical_hash`].
+    pub hash: u64,
+
+    /// Supplied when the record was added, e.g. to say which input it came from.
+    pub tag: u32,
+
+    /// Index into the grouper's source paths.
+    source: usize,
+
+    offset: u64,
+    length: u64,
+}
+
+struct Partition {
+    data: BufWriter<File>,
+    index: BufWriter<File>,
+    offset: u64,
+}
+
+/// Collects records into partitions on disk, ready to be grouped by DOI.
+pub struct Grouper {
+    /// Removed when dropped.
+    dir: TempDir,
+
+    partitions: Vec<Partition>,
+
+    /// Source paths of records, referred to by index so they don't have to be stored with each record.
+    sources: Vec<Arc<Path>>,
+    source_indexes: HashMap<Arc<Path>, usize>,
+
+    count: usize,
+}
+
+impl Grouper {
+    /// Create a grouper with partition files in a new temporary directory inside `temp_dir`.
+    /// More partitions means less memory is used, but each has a data file and an index file open while records are added.
+    pub fn new(temp_dir: &Path, partitions: usize) -> anyhow::Result<Grouper> {
+        if partitions == 0 {
+            return Err(anyhow::format_err!("Need at least one partition"));
+        }
+
+        let dir = tempfile::Builder::new()
+            .prefix("pardalotus_group")
+            .tempdir_in(temp_dir)
+            .map_err(|err| {
+                anyhow::Error::from(err).context(format!(
+                    "Failed to create temporary directory in {:?}",
+                    temp_dir
+                ))
+            })?;
+
+        let partitions = (0..partitions)
+            .map(|i| {
+                Ok(Partition {
+                    data: BufWriter::new(File::create(data_path(dir.path(), i))?),
+                    index: BufWriter::new(File::create(index_path(dir.path(), i))?),
+                    offset: 0,
+                })
+            })
+            .collect::<anyhow::Result<Vec<Partition>>>()?;
+
+        Ok(Grouper {
+            dir,
+            partitions,
+            sources: vec![],
+            source_indexes: HashMap::new(),
+            count: 0,
+        })
+    }
+
+    /// Add a record, storing it in its partition, with a tag that's returned with its [`Entry`].
+    pub fn add(&mut self, record: &Record, tag: u32) -> anyhow::Result<()> {
+        let doi = record.doi();
+        let partition_count = self.partitions.len();
+        let partition = &mut self.partitions[partition_for(doi.as_deref(), partition_count)];
+
+        let source = match self.source_indexes.get(&record.source) {
+            Some(source) => *source,
+            None => {
+                let source = self.sources.len();
+                self.sources.push(record.source.clone());
+                self.source_indexes.insert(record.source.clone(), source);
+                source
+            }
+        };
+
+        let json = serde_json::to_vec(&record.value)?;
+        partition.data.write_all(&json)?;
+
+        let entry = Entry {
+            doi,
+            updated: updated_key(&record.value),
+            hash: canonical_hash(&record.value),
+            tag,
+            source,
+            offset: partition.offset,
+            length: json.len() as u64,
+        };
+        serde_json::to_writer(&mut partition.index, &entry)?;
+        partition.index.write_all(b"\n")?;
+
+        partition.offset += json.len() as u64;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    /// Number of records added.
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Finish adding records, and iterate over them grouped by DOI.
+    pub fn finish(self) -> anyhow::Result<Groups> {
+        let partitions = self.partitions.len();
+
+        for mut partition in self.partitions {
+            partition.data.flush()?;
+            partition.index.flush()?;
+        }
+
+        Ok(Groups {
+            dir: self.dir,
+            sources: self.sources,
+            partitions,
+            next_partition: 0,
+            groups: vec![].into_iter(),
+            data: None,
+            position: 0,
+        })
+    }
+}
+
+/// Iterator over the entries for each DOI, from [`Grouper::finish`].
+///
+/// Each group holds the entries for one DOI, in the order they were added.
+/// Records without DOIs are each in a group of their own.
+/// Groups are yielded partition by partition, in order of their first record within each partition.
+pub struct Groups {
+    dir: TempDir,
+    sources: Vec<Arc<Path>>,
+    partitions: usize,
+    next_partition: usize,
+
+    /// Groups still to yield from the current partition.
+    groups: std::vec::IntoIter<Vec<Entry>>,
+
+    /// The current partition's data file.
+    data: Option<BufReader<File>>,
+
+    /// Position in the current data file.
+    position: u64,
+}
+
+impl Groups {
+    /// Read the record for an entry in the most recently yielded group.
+    pub fn read(&mut self, entry: &Entry) -> anyhow::Result<Record> {
+        let data = self
+            .data
+            .as_mut()
+            .ok_or_else(|| anyhow::format_err!("No partition loaded"))?;
+
+        // Relative, so that the buffer is kept when skipping forward a little.
+        data.seek_relative(entry.offset as i64 - self.position as i64)?;
+
+        let mut json = vec![0; entry.length as usize];
+        data.read_exact(&mut json)?;
+        self.position = entry.offset + entry.length;
+
+        let value: Value = serde_json::from_slice(&json)?;
+        Ok(Record::new(self.sources[entry.source].clone(), value))
+    }
+
+    /// Group the entries in the partition by DOI, from its index.
+    fn load_partition(&mut self, partition: usize) -> anyhow::Result<()> {
+        let mut by_doi: HashMap<String, Vec<Entry>> = HashMap::new();
+        let mut groups = vec![];
+
+        let index = BufReader::new(File::open(index_path(self.dir.path(), partition))?);
+        for line in index.lines() {
+            let entry: Entry = serde_json::from_str(&line?)?;
+
+            match entry.doi {
+                Some(ref doi) => by_doi.entry(doi.clone()).or_default().push(entry),
+                None => groups.push(vec![entry]),
+            }
+        }
+
+        groups.extend(by_doi.into_values());
+        groups.sort_unstable_by_key(|group| group[0].offset);
+
+        self.groups = groups.into_iter();
+        self.data = Some(BufReader::new(File::open(data_path(
+            self.dir.path(),
+            partition,
+        ))?));
+        self.position = 0;
+
+        Ok(())
+    }
+}
+
+impl Iterator for Groups {
+    type Item = anyhow::Result<Vec<Entry>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(group) = self.groups.next() {
+                return Some(Ok(group));
+            }
+
+            if self.next_partition >= self.partitions {
+                return None;
+            }
+
+            let partition = self.next_partition;
+            self.next_partition += 1;
+            if let Err(err) = self.load_partition(partition) {
+                return Some(Err(
+                    err.context(format!("Failed to read partition {}", partition))
+                ));
+            }
+        }
+    }
+}
+
+/// The newest entry in a group, by updated timestamp.
+/// An entry without a timestamp is older than any with one.
+/// Where entries have the same timestamp, or none, the one added last wins.
+pub fn newest<'a>(entries: impl IntoIterator<Item = &'a Entry>) -> Option<&'a Entry> {
+    // Returns the last of equal maximums.
+    entries.into_iter().max_by(|a, b| a.updated.cmp(&b.updated))
+}
+
+/// Hash of a JSON value that doesn't depend on the order of keys in objects.
+pub fn canonical_hash(value: &Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hash_value(value, &mut hasher);
+    hasher.finish()
+}
+
+fn hash_value(value: &Value, hasher: &mut DefaultHasher) {
+    match value {
+        Value::Null => 0u8.hash(hasher),
+        Value::Bool(value) => {
+            1u8.hash(hasher);
+            value.hash(hasher);
+        }
+        Value::Number(value) => {
+            2u8.hash(hasher);
+            value.to_string().hash(hasher);
+        }
+        Value::String(value) => {
+            3u8.hash(hasher);
+            value.hash(hasher);
+        }
+        Value::Array(values) => {
+            4u8.hash(hasher);
+            values.len().hash(hasher);
+            for value in values {
+                hash_value(value, hasher);
+            }
+        }
+        Value::Object(map) => {
+            5u8.hash(hasher);
+            map.len().hash(hasher);
+
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort_unstable();
+            for key in keys {
+                key.hash(hasher);
+                hash_value(&map[key], hasher);
+            }
+        }
+    }
+}
+
+fn data_path(dir: &Path, partition: usize) -> PathBuf {
+    dir.join(format!("{}.json", partition))
+}
+
+fn index_path(dir: &Path, partition: usize) -> PathBuf {
+    dir.join(format!("{}.index.jsonl", partition))
+}
+
+/// Records without DOIs all go in the first partition.
+fn partition_for(doi: Option<&str>, partitions: usize) -> usize {
+    match doi {
+        Some(doi) => {
+            let mut hasher = DefaultHasher::new();
+            doi.hash(&mut hasher);
+            (hasher.finish() % partitions as u64) as usize
+        }
+        None => 0,
+    }
+}
+
+/// When the record was last updated, as a string that sorts in time order.
+/// Crossref `indexed` date-time, falling back to `deposited`, or DataCite `updated`.
+pub fn updated_key(record: &Value) -> Option<String> {
+    let date_time = match get_agency(record)? {
+        Agency::Crossref => record
+            .get("indexed")
+            .or_else(|| record.get("deposited"))?
+            .get("date-time")?,
+        Agency::DataCite => datacite_attributes(record).get("updated")?,
+    };
+
+    date_time.as_str().map(sortable_timestamp)
+}
+
+/// Convert an ISO 8601 date-time, such as `2024-01-31T12:00:00.5Z`, to fixed-width digits in UTC,
+/// `YYYYMMDDhhmmss` followed by nanoseconds, so that timestamps written with different precision
+/// or UTC offsets compare correctly.
+fn sortable_timestamp(date_time: &str) -> String {
+    let (date, time) = date_time.split_once('T').unwrap_or((date_time, ""));
+    let (time, offset) = match time.find(['Z', '+', '-']) {
+        Some(i) => (&time[..i], &time[i..]),
+        None => (time, ""),
+    };
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+
+    let digits = |s: &str, width: usize| -> String {
+        let digits: String = s.chars().filter(char::is_ascii_digit).take(width).collect();
+        format!("{:0<width$}", digits, width = width)
+    };
+
+    let date_time = format!("{}{}", digits(date, 8), digits(time, 6));
+    let date_time = match offset_minutes(offset) {
+        Some(offset) if offset != 0 => to_utc(&date_time, offset).unwrap_or(date_time),
+        _ => date_time,
+    };
+
+    format!("{}{}", date_time, digits(fraction, 9))
+}
+
+/// Minutes ahead of UTC for an offset like `+10:00`, `-0530` or `+01`.
+fn offset_minutes(offset: &str) -> Option<i64> {
+    let sign = match offset.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+
+    let digits: String = offset.chars().filter(char::is_ascii_digit).collect();
+    let hours: i64 = digits.get(0..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4).unwrap_or("0").parse().ok()?;
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Shift `YYYYMMDDhhmmss` digits by the offset to get UTC.
+/// None if the date isn't complete, as it can't be shifted.
+fn to_utc(date_time: &str, offset_minutes: i64) -> Option<String> {
+    let field =
+        |range: std::ops::Range<usize>| -> Option<i64> { date_time.get(range)?.parse().ok() };
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    let (hour, minute, second) = (field(8..10)?, field(10..12)?, field(12..14)?);
+    if !(1..=12).contains(&month) || day == 0 {
+        return None;
+    }
+
+    let minutes = days_from_civil(year, month, day) * 1440 + hour * 60 + minute - offset_minutes;
+    let (year, month, day) = civil_from_days(minutes.div_euclid(1440));
+    let minutes = minutes.rem_euclid(1440);
+
+    Some(format!(
+        "{:04}{:02}{:02}{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60,
+        second
+    ))
+}
+
+/// Days since 1970-01-01 in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Year, month and day for days since 1970-01-01. The inverse of [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn timestamps_sort_regardless_of_precision() {
+        assert_eq!(
+            sortable_timestamp("2024-01-31T12:00:00Z"),
+            "20240131120000000000000"
+        );
+        assert_eq!(
+            sortable_timestamp("2024-01-31T12:00:00.5Z"),
+            "20240131120000500000000"
+        );
+        assert_eq!(sortable_timestamp("2024-01-31"), "20240131000000000000000");
+
+        // Converted to UTC, including across the end of a day, month or leap year.
+        assert_eq!(
+            sortable_timestamp("2024-01-31T12:00:00+10:00"),
+            "20240131020000000000000"
+        );
+        assert_eq!(
+            sortable_timestamp("2024-01-01T05:30:00.25+0630"),
+            "20231231230000250000000"
+        );
+        assert_eq!(
+            sortable_timestamp("2024-02-28T20:00:00-05:00"),
+            "20240229010000000000000"
+        );
+        assert_eq!(
+            sortable_timestamp("2024-01-31T12:00:00+00:00"),
+            sortable_timestamp("2024-01-31T12:00:00Z")
+        );
+        assert!(
+            sortable_timestamp("2024-01-31T12:00:00+10:00")
+                < sortable_timestamp("2024-01-31T03:00:00Z")
+        );
+
+        assert!(
+            sortable_timestamp("2024-01-31T12:00:00Z")
+                < sortable_timestamp("2024-01-31T12:00:00.1Z")
+        );
+        assert!(
+            sortable_timestamp("2024-01-31T12:00:00.999Z")
+                < sortable_timestamp("2024-01-31T12:00:01Z")
+        );
+        assert!(sortable_timestamp("2024-01-31") < sortable_timestamp("2024-01-31T00:00:01Z"));
+    }
+
+    #[test]
+    fn updated_keys() {
+        let crossref = json!({
+            "DOI": "10.1/a",
+            "indexed": {"date-time": "2024-02-01T00:00:00Z"},
+            "deposited": {"date-time": "2023-01-01T00:00:00Z"},
+        });
+        assert_eq!(
+            updated_key(&crossref).as_deref(),
+            Some("20240201000000000000000")
+        );
+
+        let deposited_only =
+            json!({"DOI": "10.1/a", "deposited": {"date-time": "2023-01-01T00:00:00Z"}});
+        assert_eq!(
+            updated_key(&deposited_only).as_deref(),
+            Some("20230101000000000000000")
+        );
+
+        let datacite = json!({"attributes": {"doi": "10.1/b", "updated": "2022-01-02T03:04:05Z"}});
+        assert_eq!(
+            updated_key(&datacite).as_deref(),
+            Some("20220102030405000000000")
+        );
+
+        assert_eq!(updated_key(&json!({"DOI": "10.1/a"})), None);
+    }
+
+    #[test]
+    fn hash_ignores_key_order() {
+        let a = json!({"a": 1, "b": [1, {"c": null, "d": "x"}]});
+        let b = json!({"b": [1, {"d": "x", "c": null}], "a": 1});
+        assert_eq!(canonical_hash(&a), canonical_hash(&b));
+
+        let c = json!({"a": 1, "b": [{"c": null, "d": "x"}, 1]});
+        assert_ne!(canonical_hash(&a), canonical_hash(&c));
+        assert_ne!(canonical_hash(&json!("1")), canonical_hash(&json!(1)));
+    }
+
+    #[test]
+    fn groups_by_normalized_doi() {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Arc<Path> = Arc::from(Path::new("input.jsonl"));
+        let mut grouper = Grouper::new(dir.path(), 3).unwrap();
+
+        for value in [
+            json!({"DOI": "10.1/A", "n": 1}),
+            json!({"title": "no DOI"}),
+            json!({"DOI": "https://doi.org/10.1/a", "n": 2}),
+            json!({"DOI": "10.1/b", "n": 3}),
+            json!({"title": "no DOI either"}),
+        ] {
+            grouper.add(&Record::new(source.clone(), value), 0).unwrap();
+        }
+        assert_eq!(grouper.len(), 5);
+
+        let mut groups = grouper.finish().unwrap();
+        let mut found = vec![];
+        while let Some(group) = groups.next() {
+            let group = group.unwrap();
+            let values: Vec<Value> = group
+                .iter()
+                .map(|entry| groups.read(entry).unwrap().value["n"].clone())
+                .collect();
+            found.push((group[0].doi.clone(), values));
+        }
+        found.sort_by(|a, b| a.0.cmp(&b.0));
+
+        assert_eq!(
+            found,
+            vec![
+                (None, vec![Value::Null]),
+                (None, vec![Value::Null]),
+                (Some(String::from("10.1/a")), vec![json!(1), json!(2)]),
+                (Some(String::from("10.1/b")), vec![json!(3)]),
+            ]
+        );
+    }
+}
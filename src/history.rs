@@ -0,0 +1,339 @@
+//! Per-DOI version histories across snapshots taken at different dates.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    filter::FilterDate,
+    group::{newest, Entry, Groups},
+};
+
+/// The snapshot dates of input files, taken from their paths, and the tags that stand for them.
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    /// Snapshot date for each tag.
+    dates: Vec<FilterDate>,
+
+    tags: HashMap<FilterDate, u32>,
+
+    /// Tag for each file, or None if it has no date.
+    files: HashMap<Arc<Path>, Option<u32>>,
+
+    /// Number of records from each snapshot.
+    counts: BTreeMap<FilterDate, usize>,
+}
+
+impl Snapshots {
+    pub fn new() -> Snapshots {
+        Snapshots::default()
+    }
+
+    /// The tag for a record from this file, or None if there's no date in its path. Counts the record towards its snapshot.
+    /// The second value is true the first time a file is seen, so that files without dates can be reported once.
+    pub fn tag(&mut self, path: &Arc<Path>) -> (Option<u32>, bool) {
+        if let Some(tag) = self.files.get(path) {
+            if let Some(tag) = tag {
+                *self
+                    .counts
+                    .entry(self.dates[*tag as usize].clone())
+                    .or_insert(0) += 1;
+            }
+            return (*tag, false);
+        }
+
+        let tag = snapshot_date(path).map(|date| {
+            *self.counts.entry(date.clone()).or_insert(0) += 1;
+            *self.tags.entry(date.clone()).or_insert_with(|| {
+                self.dates.push(date);
+                (self.dates.len() - 1) as u32
+            })
+        });
+
+        self.files.insert(path.clone(), tag);
+        (tag, true)
+    }
+
+    pub fn date(&self, tag: u32) -> &FilterDate {
+        &self.dates[tag as usize]
+    }
+
+    /// Number of records from each snapshot date, in date order.
+    pub fn counts(&self) -> &BTreeMap<FilterDate, usize> {
+        &self.counts
+    }
+}
+
+/// A distinct version of a record.
+#[derive(Clone, Debug, Serialize)]
+pub struct Version {
+    /// Date of the snapshot the version first appeared in.
+    pub snapshot: String,
+
+    pub record: Value,
+}
+
+/// The versions of the record for a DOI, in snapshot order.
+#[derive(Clone, Debug, Serialize)]
+pub struct DoiHistory {
+    /// Normalized DOI.
+    pub doi: String,
+
+    pub versions: Vec<Version>,
+}
+
+/// Counts of DOIs and versions.
+#[derive(Debug, Default)]
+pub struct HistorySummary {
+    pub dois: usize,
+    pub versions: usize,
+
+    /// DOIs with more than one version.
+    pub changed: usize,
+
+    /// Records without DOIs, which have no history.
+    pub without_doi: usize,
+}
+
+impl HistorySummary {
+    pub fn new() -> HistorySummary {
+        HistorySummary::default()
+    }
+
+    /// Print the summary to STDERR, including the number of records from each snapshot.
+    pub fn print(&self, snapshots: &Snapshots) {
+        eprintln!("History:");
+        eprintln!("Snapshots:");
+        for (date, count) in snapshots.counts() {
+            eprintln!("  {}: {} records", date, count);
+        }
+        eprintln!("DOIs: {}", self.dois);
+        eprintln!("Versions: {}", self.versions);
+        eprintln!("DOIs with more than one version: {}", self.changed);
+        eprintln!("Records without DOIs: {}", self.without_doi);
+    }
+}
+
+/// The history of one DOI, from a group of records tagged by [`Snapshots::tag`].
+///
+/// Snapshots are taken in date order. Where a snapshot has more than one record for the DOI, the newest is used.
+/// A new version starts whenever the content differs from the previous snapshot's, so a record that changes
+/// and then changes back has three versions.
+/// Returns None for records without DOIs.
+pub fn history(
+    groups: &mut Groups,
+    group: &[Entry],
+    snapshots: &Snapshots,
+    summary: &mut HistorySummary,
+) -> anyhow::Result<Option<DoiHistory>> {
+    let Some(doi) = group.first().and_then(|entry| entry.doi.clone()) else {
+        summary.without_doi += group.len();
+        return Ok(None);
+    };
+
+    let mut by_snapshot: BTreeMap<&FilterDate, Vec<&Entry>> = BTreeMap::new();
+    for entry in group {
+        by_snapshot
+            .entry(snapshots.date(entry.tag))
+            .or_default()
+            .push(entry);
+    }
+
+    let mut versions = vec![];
+    let mut previous_hash = None;
+    for (date, entries) in by_snapshot {
+        let Some(entry) = newest(entries) else {
+            continue;
+        };
+
+        if previous_hash != Some(entry.hash) {
+            previous_hash = Some(entry.hash);
+            versions.push(Version {
+                snapshot: date.to_string(),
+                record: groups.read(entry)?.value,
+            });
+        }
+    }
+
+    summary.dois += 1;
+    summary.versions += versions.len();
+    if versions.len() > 1 {
+        summary.changed += 1;
+    }
+
+    Ok(Some(DoiHistory { doi, versions }))
+}
+
+/// The snapshot date in a file's path, such as `2023` in `April 2023 Public Data File` or `2024-01` in
+/// `updates_2024-01.tar.gz`. The last date in the path is used, so a date in the file name takes precedence
+/// over one in a directory name. None if there isn't one.
+///
+/// Snapshot files are often numbered chunks, like `2001.json.gz`, so a file name that's just a number doesn't count.
+pub fn snapshot_date(path: &Path) -> Option<FilterDate> {
+    let mut components = path.components().rev();
+
+    let file_name = components.next()?.as_os_str().to_string_lossy();
+    let stem = file_name.split('.').next().unwrap_or_default();
+    let numbered = !stem.is_empty() && stem.chars().all(|c| c.is_ascii_digit());
+    let from_file_name = if numbered {
+        None
+    } else {
+        find_date(&file_name)
+    };
+
+    from_file_name.or_else(|| {
+        components.find_map(|component| find_date(&component.as_os_str().to_string_lossy()))
+    })
+}
+
+/// The last date in a string: `YYYY`, `YYYY-MM`, `YYYY-MM-DD`, `YYYYMM` or `YYYYMMDD`, with `-` or `_` as separators.
+/// Years must be between 1900 and 2199, and dates must not be part of a longer run of digits.
+fn find_date(s: &str) -> Option<FilterDate> {
+    let chars: Vec<char> = s.chars().collect();
+
+    // Runs of digits, with the index they start at.
+    let mut runs: Vec<(usize, String)> = vec![];
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_digit() {
+            match runs.last_mut() {
+                Some((start, digits)) if *start + digits.len() == i => digits.push(*c),
+                _ => runs.push((i, c.to_string())),
+            }
+        }
+    }
+
+    let in_range = |digits: &str, range: std::ops::RangeInclusive<u32>| {
+        digits
+            .parse::<u32>()
+            .is_ok_and(|value| range.contains(&value))
+    };
+    let is_year = |digits: &str| digits.len() == 4 && in_range(digits, 1900..=2199);
+    let is_month = |digits: &str| digits.len() == 2 && in_range(digits, 1..=12);
+    let is_day = |digits: &str| digits.len() == 2 && in_range(digits, 1..=31);
+
+    // Whether run `j` follows run `i` after a single separator.
+    let follows = |i: usize, j: usize| {
+        runs.get(j).is_some_and(|(start, _)| {
+            *start == runs[i].0 + runs[i].1.len() + 1 && matches!(chars[start - 1], '-' | '_')
+        })
+    };
+
+    let mut found = None;
+    let mut i = 0;
+    while i < runs.len() {
+        let digits = runs[i].1.as_str();
+        let mut date = None;
+        let mut used = 1;
+
+        if is_year(digits) && follows(i, i + 1) && !is_month(&runs[i + 1].1) {
+            // Something like `2024-13` isn't a date.
+            used = 2;
+        } else if is_year(digits) {
+            let mut parts = vec![digits];
+            if follows(i, i + 1) {
+                parts.push(&runs[i + 1].1);
+                if follows(i + 1, i + 2) && is_day(&runs[i + 2].1) {
+                    parts.push(&runs[i + 2].1);
+                }
+            }
+            used = parts.len();
+            date = Some(parts.join("-"));
+        } else if digits.len() == 6 && is_year(&digits[..4]) && is_month(&digits[4..]) {
+            date = Some(format!("{}-{}", &digits[..4], &digits[4..]));
+        } else if digits.len() == 8
+            && is_year(&digits[..4])
+            && is_month(&digits[4..6])
+            && is_day(&digits[6..])
+        {
+            date = Some(format!(
+                "{}-{}-{}",
+                &digits[..4],
+                &digits[4..6],
+                &digits[6..]
+            ));
+        }
+
+        if let Some(date) = date {
+            found = date.parse().ok().or(found);
+        }
+        i += used;
+    }
+
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(path: &str) -> Option<String> {
+        snapshot_date(Path::new(path)).map(|date| date.to_string())
+    }
+
+    #[test]
+    fn date_forms() {
+        assert_eq!(
+            date("x/updates_2024-01.jsonl"),
+            Some(String::from("2024-01"))
+        );
+        assert_eq!(
+            date("x/snap_2024_02_15.jsonl"),
+            Some(String::from("2024-02-15"))
+        );
+        assert_eq!(
+            date("x/snap20240215.jsonl"),
+            Some(String::from("2024-02-15"))
+        );
+        assert_eq!(date("x/snap202402.jsonl"), Some(String::from("2024-02")));
+        assert_eq!(
+            date("April 2023 Public Data File/x.jsonl"),
+            Some(String::from("2023"))
+        );
+        assert_eq!(date("x/nodate.jsonl"), None);
+    }
+
+    #[test]
+    fn file_name_beats_directory() {
+        assert_eq!(
+            date("crossref_2023/updates_2024-01.jsonl"),
+            Some(String::from("2024-01"))
+        );
+    }
+
+    #[test]
+    fn numbered_chunks_use_directory() {
+        assert_eq!(
+            date("April 2023 Public Data File/2001.json.gz"),
+            Some(String::from("2023"))
+        );
+        assert_eq!(
+            date("April 2023 Public Data File/202001.json.gz"),
+            Some(String::from("2023"))
+        );
+        assert_eq!(date("x/2001.json.gz"), None);
+    }
+
+    #[test]
+    fn yearly_files() {
+        assert_eq!(date("x/crossref_2023.jsonl.gz"), Some(String::from("2023")));
+        assert_eq!(
+            date("x/DataCite_Public_Data_File_2023.tar.gz"),
+            Some(String::from("2023"))
+        );
+        assert_eq!(
+            date("snapshots_2022/crossref_2023.jsonl.gz"),
+            Some(String::from("2023"))
+        );
+    }
+
+    #[test]
+    fn dates_in_longer_numbers_ignored() {
+        assert_eq!(date("x/id12024-01.jsonl"), None);
+        assert_eq!(date("x/v2024-13.jsonl"), None);
+    }
+}
@@ -0,0 +1,400 @@
+//! Seekable gzip JSON Lines output with a sidecar index of DOIs, for looking up records without a full scan.
+//!
+//! The output is written as a series of gzip members, each holding whole records, about [`BLOCK_SIZE`] uncompressed.
+//! Concatenated gzip members are still a valid gzip file, so the output can be read by any gzip tool.
+//! The index is written alongside, with `.idx` appended to the file name. It maps a hash of each normalized DOI
+//! to the offset of the block holding the record. Entries are fixed-width and sorted by hash, so a lookup
+//! is a binary search of the index followed by decompressing one block.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeSet, BinaryHeap},
+    ffi::OsString,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::Receiver,
+};
+
+use serde_json::Value;
+use tempfile::TempDir;
+
+use crate::{compression::Compression, doi::normalize, metadata::get_doi_from_record};
+
+/// Approximate uncompressed size of each block. Larger blocks compress better but take longer to look up.
+pub const BLOCK_SIZE: usize = 256 * 1024;
+
+/// Identifies an index file, and its version.
+const INDEX_MAGIC: &[u8; 8] = b"PSTIDX01";
+
+/// Each entry is a DOI hash and a block offset, both big-endian u64.
+const ENTRY_SIZE: u64 = 16;
+
+/// Number of index entries sorted in memory at a time while writing. 64 MB.
+const RUN_ENTRIES: usize = 4 * 1024 * 1024;
+
+/// Path of the index for a data file: the same path with `.idx` appended.
+pub fn index_path(data_path: &Path) -> PathBuf {
+    let mut path = OsString::from(data_path.as_os_str());
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+/// Write records as seekable gzip JSON Lines, with an index of their DOIs.
+pub fn write_chan_to_indexed_jsonl(
+    output_file: &Path,
+    rx: Receiver<Value>,
+    compression_level: Option<u32>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let mut f = BufWriter::new(File::create(output_file)?);
+
+    // Runs of sorted index entries go in a temporary directory next to the index.
+    let run_dir = output_file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let mut index = IndexBuilder::new(run_dir)?;
+
+    let mut block: Vec<u8> = Vec::with_capacity(BLOCK_SIZE * 2);
+    let mut block_hashes: Vec<u64> = vec![];
+    let mut offset: u64 = 0;
+
+    let mut count: usize = 0;
+    for entry in rx.iter() {
+        serde_json::to_writer(&mut block, &entry)?;
+        block.push(b'\n');
+
+        if let Some(doi) = get_doi_from_record(&entry) {
+            block_hashes.push(doi_hash(&normalize(&doi)));
+        }
+
+        if block.len() >= BLOCK_SIZE {
+            for hash in block_hashes.drain(..) {
+                index.add(hash, offset)?;
+            }
+            offset += write_block(&mut f, &block, compression_level)?;
+            block.clear();
+        }
+
+        count += 1;
+        if verbose && count.is_multiple_of(10000) {
+            eprintln!("Written {} entries to {:?}", count, output_file);
+        }
+    }
+
+    if !block.is_empty() {
+        for hash in block_hashes.drain(..) {
+            index.add(hash, offset)?;
+        }
+        write_block(&mut f, &block, compression_level)?;
+    }
+
+    f.flush()?;
+
+    let index_file = index_path(output_file);
+    index.finish(&index_file)?;
+    if verbose {
+        eprintln!("Written index {:?}", index_file);
+    }
+
+    Ok(())
+}
+
+/// Compress a block as a gzip member and write it. Returns the compressed length.
+fn write_block(
+    f: &mut impl Write,
+    block: &[u8],
+    compression_level: Option<u32>,
+) -> anyhow::Result<u64> {
+    let mut encoder =
+        Compression::Gzip.encoder(Vec::with_capacity(block.len() / 4), compression_level)?;
+    encoder.write_all(block)?;
+    let compressed = encoder.finish()?;
+    f.write_all(&compressed)?;
+    Ok(compressed.len() as u64)
+}
+
+/// Hash of a normalized DOI, for the index. FNV-1a, because the hash must be the same across builds.
+fn doi_hash(normalized_doi: &str) -> u64 {
+    normalized_doi
+        .bytes()
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+/// Builds a sorted index file of (hash, offset) entries, using an external merge sort so that
+/// indexes of more entries than fit in memory can be built.
+struct IndexBuilder {
+    dir: TempDir,
+    entries: Vec<(u64, u64)>,
+    runs: Vec<PathBuf>,
+}
+
+impl IndexBuilder {
+    fn new(dir: &Path) -> anyhow::Result<IndexBuilder> {
+        Ok(IndexBuilder {
+            dir: tempfile::Builder::new()
+                .prefix("pardalotus_index")
+                .tempdir_in(dir)?,
+            entries: vec![],
+            runs: vec![],
+        })
+    }
+
+    fn add(&mut self, hash: u64, offset: u64) -> anyhow::Result<()> {
+        self.entries.push((hash, offset));
+        if self.entries.len() >= RUN_ENTRIES {
+            self.write_run()?;
+        }
+        Ok(())
+    }
+
+    /// Sort the entries in memory and write them to a new run file.
+    fn write_run(&mut self) -> anyhow::Result<()> {
+        self.entries.sort_unstable();
+        let path = self.dir.path().join(format!("{}.run", self.runs.len()));
+        let mut f = BufWriter::new(File::create(&path)?);
+        for (hash, offset) in self.entries.drain(..) {
+            write_entry(&mut f, hash, offset)?;
+        }
+        f.flush()?;
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// Merge the runs into the index file.
+    fn finish(mut self, index_file: &Path) -> anyhow::Result<()> {
+        if !self.entries.is_empty() {
+            self.write_run()?;
+        }
+
+        let mut runs = self
+            .runs
+            .iter()
+            .map(|path| Ok(BufReader::new(File::open(path)?)))
+            .collect::<anyhow::Result<Vec<BufReader<File>>>>()?;
+
+        let mut heap = BinaryHeap::new();
+        for (i, run) in runs.iter_mut().enumerate() {
+            if let Some(entry) = read_entry(run)? {
+                heap.push(Reverse((entry, i)));
+            }
+        }
+
+        let mut f = BufWriter::new(File::create(index_file)?);
+        f.write_all(INDEX_MAGIC)?;
+
+        let mut previous = None;
+        while let Some(Reverse((entry, i))) = heap.pop() {
+            // A DOI may appear more than once in a block.
+            if previous != Some(entry) {
+                write_entry(&mut f, entry.0, entry.1)?;
+                previous = Some(entry);
+            }
+
+            if let Some(entry) = read_entry(&mut runs[i])? {
+                heap.push(Reverse((entry, i)));
+            }
+        }
+
+        f.flush()?;
+        Ok(())
+    }
+}
+
+fn write_entry(f: &mut impl Write, hash: u64, offset: u64) -> anyhow::Result<()> {
+    f.write_all(&hash.to_be_bytes())?;
+    f.write_all(&offset.to_be_bytes())?;
+    Ok(())
+}
+
+/// Read an entry, or None at the end of the file.
+fn read_entry(f: &mut impl Read) -> anyhow::Result<Option<(u64, u64)>> {
+    let mut buf = [0; ENTRY_SIZE as usize];
+    match f.read_exact(&mut buf) {
+        Ok(()) => Ok(Some((
+            u64::from_be_bytes(buf[..8].try_into()?),
+            u64::from_be_bytes(buf[8..].try_into()?),
+        ))),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// A seekable gzip JSON Lines file and its index, opened for lookups.
+pub struct IndexedSnapshot {
+    data: File,
+    index: File,
+
+    /// Number of entries in the index.
+    len: u64,
+}
+
+impl IndexedSnapshot {
+    /// Open a file written with an index. The index is found at [`index_path`].
+    pub fn open(data_path: &Path) -> anyhow::Result<IndexedSnapshot> {
+        let index_file = index_path(data_path);
+        let mut index = File::open(&index_file).map_err(|err| {
+            anyhow::Error::from(err).context(format!(
+                "Failed to open index {:?}. Write the snapshot with --index to create one",
+                index_file
+            ))
+        })?;
+
+        let mut magic = [0; 8];
+        index.read_exact(&mut magic)?;
+        if &magic != INDEX_MAGIC {
+            return Err(anyhow::format_err!("{:?} isn't an index file", index_file));
+        }
+
+        let len = (index.metadata()?.len() - INDEX_MAGIC.len() as u64) / ENTRY_SIZE;
+
+        Ok(IndexedSnapshot {
+            data: File::open(data_path)?,
+            index,
+            len,
+        })
+    }
+
+    /// The records for a DOI, normalized before lookup. Empty if there are none.
+    pub fn lookup(&mut self, doi: &str) -> anyhow::Result<Vec<Value>> {
+        let doi = normalize(doi);
+        let hash = doi_hash(&doi);
+
+        // Find the first entry with the hash.
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.entry(middle)?.0 < hash {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        // Blocks are in order of offset, so they're read in file order.
+        let mut offsets = BTreeSet::new();
+        let mut i = low;
+        while i < self.len {
+            let (entry_hash, offset) = self.entry(i)?;
+            if entry_hash != hash {
+                break;
+            }
+            offsets.insert(offset);
+            i += 1;
+        }
+
+        let mut records = vec![];
+        for offset in offsets {
+            self.data.seek(SeekFrom::Start(offset))?;
+
+            // Reads just the one gzip member.
+            let block = flate2::read::GzDecoder::new(BufReader::new(&self.data));
+            for line in BufReader::new(block).lines() {
+                let record: Value = serde_json::from_str(&line?)?;
+
+                // Check the DOI itself, as different DOIs may have the same hash.
+                if get_doi_from_record(&record).is_some_and(|found| normalize(&found) == doi) {
+                    records.push(record);
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    fn entry(&mut self, i: u64) -> anyhow::Result<(u64, u64)> {
+        self.index
+            .seek(SeekFrom::Start(INDEX_MAGIC.len() as u64 + i * ENTRY_SIZE))?;
+        read_entry(&mut self.index)?.ok_or_else(|| anyhow::format_err!("Index entry {} missing", i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// Write records with an index, small enough to need several blocks.
+    fn write_indexed(path: &Path, records: Vec<Value>) {
+        let (tx, rx) = channel();
+        for record in records {
+            tx.send(record).unwrap();
+        }
+        drop(tx);
+        write_chan_to_indexed_jsonl(path, rx, None, false).unwrap();
+    }
+
+    #[test]
+    fn write_and_look_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl.gz");
+
+        // Padding so that records span many blocks.
+        let padding = "x".repeat(1000);
+        let mut records: Vec<Value> = (0..1000)
+            .map(|i| json!({"DOI": format!("10.5555/{}", i), "padding": padding}))
+            .collect();
+        records.push(json!({"DOI": "10.5555/ABC", "n": 1}));
+        records.push(json!({"title": "no DOI"}));
+        records.push(json!({"doi": "https://doi.org/10.5555/abc", "n": 2}));
+        write_indexed(&path, records);
+
+        let mut snapshot = IndexedSnapshot::open(&path).unwrap();
+        // Both records for 10.5555/abc are in the last block, so share an entry.
+        assert_eq!(snapshot.len, 1001);
+
+        for i in [0, 1, 499, 999] {
+            let found = snapshot.lookup(&format!("10.5555/{}", i)).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0]["DOI"], json!(format!("10.5555/{}", i)));
+        }
+
+        let found = snapshot.lookup("doi:10.5555/Abc").unwrap();
+        assert_eq!(
+            found
+                .iter()
+                .map(|record| record["n"].clone())
+                .collect::<Vec<Value>>(),
+            vec![json!(1), json!(2)]
+        );
+
+        assert!(snapshot.lookup("10.5555/1000").unwrap().is_empty());
+    }
+
+    #[test]
+    fn output_is_plain_gzip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl.gz");
+        let padding = "x".repeat(BLOCK_SIZE / 2);
+        write_indexed(
+            &path,
+            (0..5)
+                .map(|i| json!({"DOI": format!("10.5555/{}", i), "padding": padding}))
+                .collect(),
+        );
+
+        let lines = BufReader::new(flate2::read::MultiGzDecoder::new(
+            File::open(&path).unwrap(),
+        ))
+        .lines()
+        .count();
+        assert_eq!(lines, 5);
+    }
+
+    #[test]
+    fn missing_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.jsonl.gz");
+        File::create(&path).unwrap();
+        assert!(IndexedSnapshot::open(&path).is_err());
+
+        std::fs::write(index_path(&path), b"not an index").unwrap();
+        assert!(IndexedSnapshot::open(&path).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! jq expressions, evaluated with the pure-Rust jaq implementation, for selecting and reshaping records.
+
+use jaq_core::{
+    load::{self, Arena, File, Loader},
+    Compiler, Ctx, Native, RcIter,
+};
+use jaq_json::Val;
+use serde_json::Value;
+
+/// A compiled jq expression.
+pub struct JqFilter {
+    filter: jaq_core::Filter<Native<Val>>,
+}
+
+impl JqFilter {
+    /// Compile a jq expression, with the jq standard library available.
+    pub fn compile(code: &str) -> anyhow::Result<JqFilter> {
+        let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+        let arena = Arena::default();
+
+        let modules = loader
+            .load(&arena, File { code, path: () })
+            .map_err(|errors| {
+                let messages: Vec<String> = errors
+                    .into_iter()
+                    .flat_map(|(_, error)| load_error_messages(code, error))
+                    .collect();
+                anyhow::format_err!(
+                    "Couldn't parse jq expression {:?}: {}",
+                    code,
+                    messages.join(", ")
+                )
+            })?;
+
+        let filter = Compiler::default()
+            .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+            .compile(modules)
+            .map_err(|errors| {
+                let messages: Vec<String> = errors
+                    .into_iter()
+                    .flat_map(|(_, errors)| errors)
+                    .map(|(name, undefined)| format!("undefined {} {}", undefined.as_str(), name))
+                    .collect();
+                anyhow::format_err!(
+                    "Couldn't compile jq expression {:?}: {}",
+                    code,
+                    messages.join(", ")
+                )
+            })?;
+
+        Ok(JqFilter { filter })
+    }
+
+    /// All outputs of the expression for this input, in order.
+    pub fn run(&self, input: &Value) -> anyhow::Result<Vec<Value>> {
+        let inputs = RcIter::new(core::iter::empty());
+
+        self.filter
+            .run((Ctx::new([], &inputs), Val::from(input.clone())))
+            .map(|output| {
+                output
+                    .map(Value::from)
+                    .map_err(|err| anyhow::format_err!("jq error: {}", err))
+            })
+            .collect()
+    }
+
+    /// Whether any output of the expression for this input is truthy, i.e. not false or null.
+    /// This is the test applied by jq's `select`.
+    pub fn matches(&self, input: &Value) -> anyhow::Result<bool> {
+        let outputs = self.run(input)?;
+
+        Ok(outputs
+            .iter()
+            .any(|output| !matches!(output, Value::Null | Value::Bool(false))))
+    }
+}
+
+/// Describe a lexing or parsing error, with its position in the expression.
+fn load_error_messages(code: &str, error: load::Error<&str>) -> Vec<String> {
+    match error {
+        load::Error::Io(errors) => errors
+            .into_iter()
+            .map(|(path, error)| format!("{}: {}", path, error))
+            .collect(),
+        load::Error::Lex(errors) => errors
+            .into_iter()
+            .map(|(expect, at)| {
+                format!(
+                    "expected {} at position {}",
+                    expect.as_str(),
+                    load::span(code, at).start
+                )
+            })
+            .collect(),
+        load::Error::Parse(errors) => errors
+            .into_iter()
+            .map(|(expect, at)| {
+                format!(
+                    "expected {} at position {}",
+                    expect.as_str(),
+                    load::span(code, at).start
+                )
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn run_outputs_in_order() {
+        let record = json!({"DOI": "10.1/a", "author": [{"family": "A"}, {"family": "B"}]});
+
+        let filter = JqFilter::compile(".author[].family").unwrap();
+        assert_eq!(filter.run(&record).unwrap(), vec![json!("A"), json!("B")]);
+
+        let filter = JqFilter::compile("{doi: .DOI, n: (.author | length)}").unwrap();
+        assert_eq!(
+            filter.run(&record).unwrap(),
+            vec![json!({"doi": "10.1/a", "n": 2})]
+        );
+
+        assert!(JqFilter::compile("empty")
+            .unwrap()
+            .run(&record)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn matches_if_any_output_truthy() {
+        let record = json!({"a": 1, "b": false, "c": null, "d": [false, 0]});
+        let matches = |code: &str| JqFilter::compile(code).unwrap().matches(&record).unwrap();
+
+        assert!(matches(".a"));
+        assert!(!matches(".b"));
+        assert!(!matches(".c"));
+        assert!(!matches(".missing"));
+        assert!(!matches("empty"));
+
+        // 0 is truthy in jq.
+        assert!(matches(".d[]"));
+        assert!(!matches(".b, .c"));
+        assert!(matches(".b, .a"));
+    }
+
+    #[test]
+    fn errors() {
+        let err = JqFilter::compile(".a |").err().unwrap();
+        assert!(err.to_string().contains("Couldn't parse"), "{}", err);
+
+        let err = JqFilter::compile("nonexistent(1)").err().unwrap();
+        assert!(err.to_string().contains("Couldn't compile"), "{}", err);
+        assert!(err.to_string().contains("nonexistent"), "{}", err);
+
+        let filter = JqFilter::compile(".a + 1").unwrap();
+        assert!(filter.run(&json!({"a": "x"})).is_err());
+    }
+}
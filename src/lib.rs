@@ -0,0 +1,44 @@
+//! Tools to work with scholarly metadata snapshots from DataCite and Crossref.
+//!
+//! Use a [`SnapshotReader`] to iterate over all the [`Record`]s in a snapshot file or directory of snapshot files.
+
+pub mod compression;
+pub mod dedupe;
+pub mod detect;
+pub mod diff;
+pub mod doi;
+pub mod doi_list;
+pub mod errors;
+pub mod filter;
+pub mod group;
+pub mod history;
+pub mod index;
+pub mod jq;
+pub mod metadata;
+pub mod model;
+pub mod query;
+pub mod read;
+pub mod record;
+pub mod sqlite;
+pub mod stats;
+pub mod store;
+pub mod update;
+pub mod validate;
+pub mod write;
+
+pub use errors::ErrorPolicy;
+pub use filter::RecordFilter;
+pub use metadata::Agency;
+pub use model::{common::CommonRecord, TypedRecord};
+pub use read::{Records, SnapshotReader};
+pub use record::Record;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Path that stands for STDIN as an input, or STDOUT as an output.
+pub const STDIO_PATH: &str = "-";
+
+/// Whether the path stands for STDIN or STDOUT.
+pub fn is_stdio(path: &std::path::Path) -> bool {
+    path.as_os_str() == STDIO_PATH
+}
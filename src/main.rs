@@ -1,26 +1,41 @@
-mod metadata;
-mod read;
-mod write;
-
 use std::{
-    collections::BTreeMap,
-    fs::{self},
-    path::PathBuf,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
     process::exit,
-    sync::mpsc::{self, Receiver, SyncSender},
-    thread,
+    sync::{
+        mpsc::{self, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
 };
 
-use metadata::get_doi_from_record;
-use read::read_paths_to_channel;
+use pardalotus_snapshot_tool::{
+    compression::Compression,
+    dedupe::Deduplicator,
+    detect::SkippedFile,
+    diff::{self, DiffSummary},
+    doi_list::DoiSet,
+    errors::{ErrorLog, ReadError},
+    filter::{DateRange, FilterDate},
+    group::Grouper,
+    history::{self, HistorySummary, Snapshots},
+    index::IndexedSnapshot,
+    is_stdio,
+    jq::JqFilter,
+    query,
+    read::CHANNEL_SIZE,
+    stats::Stats,
+    store::SnapshotStore,
+    update::{self, UpdateSummary},
+    validate::DoiValidator,
+    write::{write_chan_to_file, OutputFormat, WriteOptions},
+    Agency, ErrorPolicy, Record, RecordFilter, SnapshotReader, STDIO_PATH, VERSION,
+};
 use serde_json::Value;
 use structopt::StructOpt;
 
-use write::write_chan_to_json_gz;
-
-pub const VERSION: &str = env!("CARGO_PKG_VERSION");
-
-#[derive(Debug, StructOpt)]
+#[derive(Clone, Debug, StructOpt)]
 #[structopt(name = "pardalotus_snapshot_tool", about = "Pardalotus Snapshot Tool")]
 struct Options {
     #[structopt(long, help("Show version"))]
@@ -32,7 +47,7 @@ struct Options {
     #[structopt(
         long,
         parse(from_os_str),
-        help("Input directory containing snapshot files.")
+        help("Input directory containing snapshot files, or a single file. Use - to read from STDIN.")
     )]
     input: Option<PathBuf>,
 
@@ -45,12 +60,218 @@ struct Options {
     #[structopt(
         long,
         short = "o",
-        help("Save to output file, combining all inputs. Only .jsonl.gz currently supported.")
+        help("Save to output file, combining all inputs. The format is chosen by extension: .jsonl, .jsonl.gz, .jsonl.zst, .jsonl.xz, .jsonl.bz2, .csv, .parquet, .redb, .sqlite or .db. Use - to write JSON Lines to STDOUT.")
     )]
     output_file: Option<PathBuf>,
 
-    #[structopt(long, help("Print list of DOIs for all records to STDOUT."))]
+    #[structopt(
+        long,
+        help("With a .jsonl.gz --output-file, write it in independently compressed blocks, with an index of DOIs in a file with .idx appended to the name, for use with --lookup.")
+    )]
+    index: bool,
+
+    #[structopt(
+        long,
+        number_of_values = 1,
+        help("Look up the records for this DOI in --input, a .jsonl.gz file written with --index or a .redb file, without reading the whole file. Writes them to --output-file, or STDOUT as JSON Lines. Repeat to look up several DOIs.")
+    )]
+    lookup: Vec<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("Look up the records for each DOI in this file, one per line, as for --lookup.")
+    )]
+    lookup_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help("With a JSON Lines --output-file, write records normalized to a common schema for Crossref and DataCite, instead of the raw JSON. Unrecognised records are skipped.")
+    )]
+    normalize: bool,
+
+    #[structopt(
+        long,
+        default_value = "100000",
+        help("With a .parquet --output-file, the maximum number of rows per row group.")
+    )]
+    row_group_size: usize,
+
+    #[structopt(
+        long,
+        help("With a compressed JSON Lines --output-file, the compression level. Defaults to 9 for gzip, 3 for zstd, 6 for xz and 9 for bzip2.")
+    )]
+    compression_level: Option<u32>,
+
+    #[structopt(
+        long,
+        help("Print list of DOIs for all records to STDOUT, normalized: lowercase, without any https://doi.org/ prefix.")
+    )]
     print_dois: bool,
+
+    #[structopt(
+        long,
+        help("Report records with missing or malformed DOIs, or DOIs with non-ASCII or control characters, to STDOUT. Grouped by source file and prefix.")
+    )]
+    validate_dois: bool,
+
+    #[structopt(
+        long,
+        help("Number of input files to read in parallel. Defaults to the number of CPUs.")
+    )]
+    threads: Option<usize>,
+
+    #[structopt(
+        long,
+        help("Emit records in a stable order: sorted input files, and records in file order.")
+    )]
+    deterministic: bool,
+
+    #[structopt(
+        long,
+        default_value = "skip",
+        help("What to do when a record or file can't be read: \"skip\" it and carry on, or \"fail\" with a nonzero exit code.")
+    )]
+    on_error: ErrorPolicy,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("Write a JSON Lines log of read errors, with the file, tar entry, line number and error.")
+    )]
+    error_log: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help("Only include records with these DOI prefixes, e.g. 10.5281. Repeat or separate with commas.")
+    )]
+    filter_prefix: Vec<String>,
+
+    #[structopt(
+        long,
+        help("Only include records from this agency: \"crossref\" or \"datacite\".")
+    )]
+    filter_agency: Option<Agency>,
+
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help("Only include records with these types: Crossref type, e.g. journal-article, or DataCite resourceTypeGeneral, e.g. Dataset. Repeat or separate with commas.")
+    )]
+    filter_type: Vec<String>,
+
+    #[structopt(
+        long,
+        help("Only include records published on or after this date: YYYY, YYYY-MM or YYYY-MM-DD.")
+    )]
+    filter_published_from: Option<FilterDate>,
+
+    #[structopt(
+        long,
+        help(
+            "Only include records published on or before this date: YYYY, YYYY-MM or YYYY-MM-DD."
+        )
+    )]
+    filter_published_until: Option<FilterDate>,
+
+    #[structopt(
+        long,
+        help("Only include records updated on or after this date: YYYY, YYYY-MM or YYYY-MM-DD.")
+    )]
+    filter_updated_from: Option<FilterDate>,
+
+    #[structopt(
+        long,
+        help("Only include records updated on or before this date: YYYY, YYYY-MM or YYYY-MM-DD.")
+    )]
+    filter_updated_until: Option<FilterDate>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("Only include records with DOIs listed in this file, one per line. DOIs are normalized, so URLs and any case are accepted.")
+    )]
+    doi_list: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("Exclude records with DOIs listed in this file, one per line.")
+    )]
+    exclude_doi_list: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("With --doi-list, write the DOIs from the list that weren't found in the input to this file.")
+    )]
+    doi_list_not_found: Option<PathBuf>,
+
+    #[structopt(
+        long = "where",
+        help("Only include records for which this jq expression is true, e.g. '.publisher == \"Zenodo\"'. Evaluated after the --filter-* options.")
+    )]
+    r#where: Option<String>,
+
+    #[structopt(
+        long,
+        help("Reshape each record with this jq expression, e.g. '{DOI, title}'. Each output becomes a record. Applied after --where.")
+    )]
+    select: Option<String>,
+
+    #[structopt(
+        long,
+        help("Keep only the newest record for each DOI, by Crossref indexed or deposited date, or DataCite updated date. Records are stored in temporary files until all input is read.")
+    )]
+    dedupe: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("With --dedupe, --diff, --history or --update, directory for temporary files. Needs space for all records, uncompressed. Defaults to the system temporary directory.")
+    )]
+    temp_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "128",
+        help("With --dedupe, --diff, --history or --update, the number of partitions records are split into. Increase to use less memory for large inputs.")
+    )]
+    partitions: usize,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help("Compare --input, the old snapshots, with these new snapshots, by DOI. Writes the DOIs added, removed and modified as JSON Lines to --output-file, or STDOUT, and a summary to STDERR.")
+    )]
+    diff: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help("With --diff, include the top-level keys that changed in each modified record, and count them in the summary.")
+    )]
+    diff_keys: bool,
+
+    #[structopt(
+        long,
+        help("Write the history of each DOI across the snapshots in --input as JSON Lines to --output-file, or STDOUT: each distinct version of its record, with the date of the snapshot it first appeared in. Snapshot dates are taken from file and directory names, e.g. 2023 or 2024-01.")
+    )]
+    history: bool,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        number_of_values = 1,
+        help("Apply these update files or directories on top of the base snapshot in --input, writing the result to --output-file, which is required. A record replaces the base record for its DOI if its updated date is later. Repeat to apply several updates, in order.")
+    )]
+    update: Vec<PathBuf>,
+
+    #[structopt(
+        long,
+        help("Run this SQL query over the records in --input, as the table \"works\", without exporting them first. Writes each result row as a JSON object to --output-file, or STDOUT as JSON Lines. The --filter-* options are applied first.")
+    )]
+    query: Option<String>,
 }
 
 fn main() {
@@ -74,237 +295,965 @@ fn main_r() -> anyhow::Result<()> {
         main_list_input_files(&options)?;
     }
 
-    if options.stats {
-        main_stats(&options)?;
+    if !options.lookup.is_empty() || options.lookup_file.is_some() {
+        main_lookup(&options)?;
+    } else if let Some(ref sql) = options.query {
+        main_query(&options, sql)?;
+    } else if let Some(ref new_input) = options.diff {
+        main_diff(&options, new_input)?;
+    } else if options.history {
+        main_history(&options)?;
+    } else if !options.update.is_empty() {
+        main_update(&options)?;
+    } else if options.stats
+        || options.print_dois
+        || options.validate_dois
+        || options.output_file.is_some()
+    {
+        main_process(&options)?;
     }
 
-    if options.print_dois {
-        main_print_dois(&options)?;
+    Ok(())
+}
+
+fn main_list_input_files(options: &Options) -> Result<(), anyhow::Error> {
+    let found = expect_reader(options, RecordFilter::default())?.files()?;
+    for file in found.files {
+        println!("{}", file.path.display())
     }
+    report_skipped_files(&found.skipped);
+    Ok(())
+}
+
+/// Read the input once, feeding each record to every requested sink: stats, DOI printing and output file.
+fn main_process(options: &Options) -> Result<(), anyhow::Error> {
+    let verbose = options.verbose;
+    let filter = record_filter(options)?;
+    let reader = expect_reader(options, filter.clone())?;
 
     if let Some(ref output_file) = options.output_file {
-        main_output_file(&options, output_file)?;
+        if is_stdio(output_file) {
+            if options.stats || options.print_dois || options.validate_dois {
+                return Err(anyhow::format_err!(
+                    "Can't write output to STDOUT with --stats, --print-dois or --validate-dois, which also use STDOUT"
+                ));
+            }
+        } else if !is_stdio(reader.path()) && output_file.starts_with(reader.path()) {
+            eprint!(
+                "Output file {:?} can't be in the input directory {:?}",
+                output_file,
+                reader.path()
+            );
+            exit(1);
+        }
+    }
+
+    check_output(options)?;
+
+    let mut dedupe = options
+        .dedupe
+        .then(|| Deduplicator::new(&temp_dir(options), options.partitions))
+        .transpose()?;
+
+    let mut records = reader.records()?;
+
+    let writer = spawn_writer(options);
+
+    let mut stats = options.stats.then(Stats::new);
+    let mut validator = options.validate_dois.then(DoiValidator::new);
+
+    let where_filter = options
+        .r#where
+        .as_deref()
+        .map(JqFilter::compile)
+        .transpose()?;
+    let select = options
+        .select
+        .as_deref()
+        .map(JqFilter::compile)
+        .transpose()?;
+
+    // Buffered because DOIs are printed in bulk.
+    // Not locked, as the writer may be using STDOUT, in which case nothing is printed here.
+    let mut stdout = BufWriter::new(io::stdout());
+
+    // Feed a record to each of the requested sinks. False if the output has been closed, so reading should stop.
+    let mut sink = |record: Record| -> anyhow::Result<bool> {
+        if let Some(ref mut stats) = stats {
+            stats.add(&record.value);
+        }
+
+        if let Some(ref mut validator) = validator {
+            validator.add(&record);
+        }
+
+        if options.print_dois {
+            if let Some(doi) = record.doi() {
+                match writeln!(stdout, "{}", doi) {
+                    // The next stage of a pipeline stopped reading, e.g. `head`.
+                    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(false),
+                    result => result?,
+                }
+            }
+        }
+
+        if let Some((ref write_tx, _)) = writer {
+            let value = if options.normalize {
+                match record.to_common() {
+                    Some(common) => serde_json::to_value(common)?,
+                    None => return Ok(true),
+                }
+            } else {
+                record.value
+            };
+
+            // If the writer has stopped, its error is reported when it's joined.
+            if write_tx.send(value).is_err() {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    };
+
+    let mut count: usize = 0;
+    let mut read_result = Ok(());
+    'records: while let Some(record) = records.next() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                read_result = Err(err);
+                break;
+            }
+        };
+
+        count += 1;
+        if verbose && count.is_multiple_of(10000) {
+            eprintln!("Read {} lines", count);
+        }
+
+        let source = record.source.clone();
+        let doi = record.doi();
+        let jq_outputs = match apply_jq(record, where_filter.as_ref(), select.as_ref()) {
+            Ok(outputs) => outputs,
+            Err(err) => {
+                // Subject to the same error policy as records that can't be parsed.
+                let error = ReadError {
+                    file: source.to_path_buf(),
+                    entry: None,
+                    line: None,
+                    error: format!("{:#} in record {:?}", err, doi.unwrap_or_default()),
+                    record_only: true,
+                };
+                if let Err(err) = records.errors().report(error) {
+                    read_result = Err(err);
+                    break;
+                }
+                continue;
+            }
+        };
+
+        for record in jq_outputs {
+            // Records are held back until all have been read, when the newest for each DOI is known.
+            if let Some(ref mut dedupe) = dedupe {
+                dedupe.add(&record)?;
+            } else if !sink(record)? {
+                break 'records;
+            }
+        }
+    }
+
+    let mut duplicates_removed = None;
+    if let (Some(dedupe), Ok(())) = (dedupe, &read_result) {
+        if verbose {
+            eprintln!("Deduplicating {} records", dedupe.len());
+        }
+
+        let mut deduped = dedupe.finish()?;
+        for record in &mut deduped {
+            if !sink(record?)? {
+                break;
+            }
+        }
+        duplicates_removed = Some(deduped.removed());
+    }
+
+    join_writer(writer)?;
+
+    read_result?;
+
+    match stdout.into_inner() {
+        Ok(_) => (),
+        Err(err) if err.error().kind() == io::ErrorKind::BrokenPipe => (),
+        Err(err) => return Err(err.into_error().into()),
+    }
+
+    let errors = records.errors();
+
+    if let Some(stats) = stats {
+        stats.print(errors);
+    }
+
+    if let Some(validator) = validator {
+        if options.stats {
+            println!();
+        }
+        validator.print();
+    }
+
+    report_skipped_files(records.skipped());
+    report_doi_lists(&filter, options.doi_list_not_found.as_deref())?;
+
+    if let Some(duplicates_removed) = duplicates_removed {
+        eprintln!("Removed {} duplicate records.", duplicates_removed);
     }
 
+    report_errors(errors);
+
     Ok(())
 }
 
-fn main_list_input_files(options: &Options) -> Result<(), anyhow::Error> {
-    let (_, paths) = expect_input_files(options)?;
-    for path in paths {
-        if let Some(path_str) = path.to_str() {
-            println!("{}", path_str)
+/// Compare --input, the old snapshots, with --diff, the new ones, writing an entry for each DOI that changed.
+fn main_diff(options: &Options, new_input: &Path) -> anyhow::Result<()> {
+    check_standalone(options, "--diff")?;
+
+    let filter = record_filter(options)?;
+    let errors = shared_error_log(options)?;
+    let old_reader = expect_reader(options, filter.clone())?.errors(errors.clone());
+    let new_reader = configure_reader(options, new_input, filter.clone()).errors(errors.clone());
+
+    check_jsonl_output(options, "--diff")?;
+
+    let mut grouper = Grouper::new(&temp_dir(options), options.partitions)?;
+    for (tag, reader) in [(diff::OLD, &old_reader), (diff::NEW, &new_reader)] {
+        let mut records = reader.records()?;
+        for record in records.by_ref() {
+            grouper.add(&record?, tag)?;
+
+            if options.verbose && grouper.len().is_multiple_of(10000) {
+                eprintln!("Read {} lines", grouper.len());
+            }
+        }
+
+        report_skipped_files(records.skipped());
+    }
+    report_errors(&errors);
+
+    let writer = spawn_writer_or_stdout(options);
+
+    let mut summary = DiffSummary::new();
+    let mut groups = grouper.finish()?;
+    while let Some(group) = groups.next() {
+        let Some(entry) = diff::compare(&mut groups, &group?, options.diff_keys, &mut summary)?
+        else {
+            continue;
+        };
+
+        if let Some((ref write_tx, _)) = writer {
+            // If the writer has stopped, its error is reported when it's joined.
+            if write_tx.send(serde_json::to_value(entry)?).is_err() {
+                break;
+            }
         }
     }
+
+    join_writer(writer)?;
+
+    report_doi_lists(&filter, options.doi_list_not_found.as_deref())?;
+    summary.print();
+
     Ok(())
 }
 
-fn main_stats(options: &Options) -> Result<(), anyhow::Error> {
-    let verbose = options.verbose;
-    let (_, paths) = expect_input_files(options)?;
-    let (tx, rx): (SyncSender<Value>, Receiver<Value>) = mpsc::sync_channel(10);
-    let read_thread = thread::spawn(move || {
-        if let Err(err) = read_paths_to_channel(&paths, tx, verbose) {
-            eprintln!("Failed read archives: {:?}", err);
-        }
-    });
-    let mut count: usize = 0;
+/// Write the history of each DOI across the snapshots in --input, tagged by the dates in their paths.
+fn main_history(options: &Options) -> anyhow::Result<()> {
+    check_standalone(options, "--history")?;
 
-    let mut total_json_chars: usize = 0;
-    let mut total_doi_bytes: usize = 0;
-    let mut total_doi_chars: usize = 0;
-    let mut doi_chars_frequencies = BTreeMap::<usize, usize>::new();
-    let mut doi_bytes_frequencies = BTreeMap::<usize, usize>::new();
-    let mut json_chars_frequencies = BTreeMap::<usize, usize>::new();
-    let mut max_doi_codepoint: char = '\0';
+    let filter = record_filter(options)?;
+    let reader = expect_reader(options, filter.clone())?;
+    check_jsonl_output(options, "--history")?;
 
-    for record in rx.iter() {
-        count += 1;
+    let mut grouper = Grouper::new(&temp_dir(options), options.partitions)?;
+    let mut snapshots = Snapshots::new();
+    let mut records = reader.records()?;
+    for record in records.by_ref() {
+        let record = record?;
 
-        if verbose && count % 10000 == 0 {
-            eprintln!("Read {} lines", count);
+        match snapshots.tag(&record.source) {
+            (Some(tag), _) => grouper.add(&record, tag)?,
+            (None, true) => eprintln!("Skipped {:?}: no snapshot date in path", record.source),
+            (None, false) => (),
         }
 
-        let json_chars = record.to_string().len();
-        total_json_chars += json_chars;
+        if options.verbose && grouper.len().is_multiple_of(10000) {
+            eprintln!("Read {} lines", grouper.len());
+        }
+    }
 
-        // Integer division to bucket into 1kb buckets.
-        let json_chars_bucketed = (json_chars / 1024) * 1024;
-        *json_chars_frequencies
-            .entry(json_chars_bucketed)
-            .or_insert(0) += 1;
+    report_skipped_files(records.skipped());
+    report_errors(records.errors());
 
-        if let Some(doi) = get_doi_from_record(&record) {
-            let doi_chars = doi.chars().count();
+    let writer = spawn_writer_or_stdout(options);
 
-            // String::len() measures bytes not chars.
-            let doi_bytes = doi.len();
+    let mut summary = HistorySummary::new();
+    let mut groups = grouper.finish()?;
+    while let Some(group) = groups.next() {
+        let Some(history) = history::history(&mut groups, &group?, &snapshots, &mut summary)?
+        else {
+            continue;
+        };
 
-            if let Some(this_max_doi_codepoint) = doi.chars().max() {
-                max_doi_codepoint = this_max_doi_codepoint.max(max_doi_codepoint);
+        if let Some((ref write_tx, _)) = writer {
+            // If the writer has stopped, its error is reported when it's joined.
+            if write_tx.send(serde_json::to_value(history)?).is_err() {
+                break;
             }
+        }
+    }
+
+    join_writer(writer)?;
+
+    report_doi_lists(&filter, options.doi_list_not_found.as_deref())?;
+    summary.print(&snapshots);
+
+    Ok(())
+}
+
+/// Apply the --update inputs on top of the base snapshot in --input, writing the consolidated snapshot to --output-file.
+fn main_update(options: &Options) -> anyhow::Result<()> {
+    check_standalone(options, "--update")?;
+
+    if options.output_file.is_none() {
+        return Err(anyhow::format_err!(
+            "--update needs an --output-file for the updated snapshot"
+        ));
+    }
+
+    let filter = record_filter(options)?;
+    let errors = shared_error_log(options)?;
+    let mut readers = vec![expect_reader(options, filter.clone())?.errors(errors.clone())];
+    for update in options.update.iter() {
+        readers.push(configure_reader(options, update, filter.clone()).errors(errors.clone()));
+    }
 
-            total_doi_chars += doi_chars;
-            *doi_chars_frequencies.entry(doi_chars).or_insert(0) += 1;
+    check_output(options)?;
 
-            total_doi_bytes += doi_bytes;
-            *doi_bytes_frequencies.entry(doi_bytes).or_insert(0) += 1;
+    let mut grouper = Grouper::new(&temp_dir(options), options.partitions)?;
+    for (tag, reader) in (update::BASE..).zip(readers.iter()) {
+        let mut records = reader.records()?;
+        for record in records.by_ref() {
+            grouper.add(&record?, tag)?;
+
+            if options.verbose && grouper.len().is_multiple_of(10000) {
+                eprintln!("Read {} lines", grouper.len());
+            }
         }
+
+        report_skipped_files(records.skipped());
     }
+    report_errors(&errors);
 
-    let mean_json_chars = (total_json_chars as f32) / (count as f32);
-    let mean_doi_chars = (total_doi_chars as f32) / (count as f32);
-    let mean_doi_bytes = (total_doi_bytes as f32) / (count as f32);
+    let writer = spawn_writer(options);
+
+    let mut summary = UpdateSummary::new();
+    let mut groups = grouper.finish()?;
+    'groups: while let Some(group) = groups.next() {
+        let group = group?;
+        for entry in update::apply(&group, &mut summary) {
+            let record = groups.read(entry)?;
+
+            if let Some((ref write_tx, _)) = writer {
+                // If the writer has stopped, its error is reported when it's joined.
+                if write_tx.send(record.value).is_err() {
+                    break 'groups;
+                }
+            }
+        }
+    }
 
-    let mode_doi_chars = doi_chars_frequencies
-        .iter()
-        .max_by_key(|&(_, count)| count)
-        .map(|(value, _)| value)
-        .unwrap_or(&0);
+    join_writer(writer)?;
 
-    let mode_doi_bytes = doi_bytes_frequencies
-        .iter()
-        .max_by_key(|&(_, count)| count)
-        .map(|(value, _)| value)
-        .unwrap_or(&0);
+    report_doi_lists(&filter, options.doi_list_not_found.as_deref())?;
+    summary.print();
 
-    let mode_json_chars = json_chars_frequencies
-        .iter()
-        .max_by_key(|&(_, count)| count)
-        .map(|(value, _)| value)
-        .unwrap_or(&0);
+    Ok(())
+}
 
-    println!("Record count: {count}");
-    println!("");
-    println!("JSON:");
-    println!("Total JSON chars: {total_json_chars}");
-    println!("Mean JSON chars: {mean_json_chars}");
-    println!("Modal JSON chars: {mode_json_chars}");
+/// Look up records by DOI in --input, using its index.
+fn main_lookup(options: &Options) -> anyhow::Result<()> {
+    check_standalone(options, "--lookup")?;
+    check_unfiltered(options, "--lookup")?;
 
-    println!("");
-    println!("DOIs:");
-    println!("Total DOI chars: {total_doi_chars}");
-    println!("Mean DOI chars: {mean_doi_chars}");
-    println!("Modal DOI chars: {mode_doi_chars}");
+    let input = options
+        .input
+        .as_ref()
+        .ok_or_else(|| anyhow::format_err!("Please supply <input>"))?;
+    check_output(options)?;
 
-    println!("");
+    let mut dois = options.lookup.clone();
+    if let Some(ref lookup_file) = options.lookup_file {
+        let f = BufReader::new(File::open(lookup_file)?);
+        for line in f.lines() {
+            let line = line?;
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                dois.push(line.to_string());
+            }
+        }
+    }
 
-    println!("Total DOI bytes: {total_doi_bytes}");
-    println!("Mean DOI bytes: {mean_doi_bytes}");
-    println!("Modal DOI bytes: {mode_doi_bytes}");
+    // A redb store, or a .jsonl.gz file with an index.
+    let mut lookup: Lookup = if OutputFormat::from_path(input).ok() == Some(OutputFormat::Redb) {
+        let store = SnapshotStore::open(input)?;
+        Box::new(move |doi| Ok(store.get(doi)?.into_iter().collect()))
+    } else {
+        let mut snapshot = IndexedSnapshot::open(input)?;
+        Box::new(move |doi| snapshot.lookup(doi))
+    };
 
-    println!(
-        "Max Unicode code point: {} : {}",
-        max_doi_codepoint, max_doi_codepoint as u32
-    );
+    let writer = spawn_writer(options);
+    // Not locked, as the writer may be using STDOUT.
+    let mut stdout = BufWriter::new(io::stdout());
 
-    println!("");
-    println!("Frequencies:");
-    println!("JSON chars frequencies (bins of 1KiB):");
+    let mut not_found = 0;
+    'dois: for doi in dois.iter() {
+        let records = lookup(doi)?;
+        if records.is_empty() {
+            eprintln!("Not found: {}", doi);
+            not_found += 1;
+        }
 
-    for (length, frequency) in json_chars_frequencies.into_iter() {
-        println!("{length},{frequency}");
+        for record in records {
+            if let Some((ref write_tx, _)) = writer {
+                // If the writer has stopped, its error is reported when it's joined.
+                if write_tx.send(record).is_err() {
+                    break 'dois;
+                }
+            } else {
+                match writeln!(stdout, "{}", record) {
+                    Err(err) if err.kind() == io::ErrorKind::BrokenPipe => break 'dois,
+                    result => result?,
+                }
+            }
+        }
     }
-    println!("");
-    println!("");
 
-    println!("DOI chars frequencies:");
+    join_writer(writer)?;
+
+    match stdout.into_inner() {
+        Ok(_) => (),
+        Err(err) if err.error().kind() == io::ErrorKind::BrokenPipe => (),
+        Err(err) => return Err(err.into_error().into()),
+    }
 
-    for (length, frequency) in doi_chars_frequencies.into_iter() {
-        println!("{length},{frequency}");
+    if options.verbose {
+        eprintln!("Found {} of {} DOIs.", dois.len() - not_found, dois.len());
     }
 
-    read_thread
-        .join()
-        .unwrap_or_else(|err| eprintln!("Failed to join reader thread: {:?}", err));
     Ok(())
 }
 
-fn main_print_dois(options: &Options) -> Result<(), anyhow::Error> {
-    let verbose = options.verbose;
-    let (_, paths) = expect_input_files(options)?;
-    let (tx, rx): (SyncSender<Value>, Receiver<Value>) = mpsc::sync_channel(10);
-    let read_thread = thread::spawn(move || {
-        if let Err(err) = read_paths_to_channel(&paths, tx, verbose) {
-            eprintln!("Failed read archives: {:?}", err);
+/// Run a SQL query over the records in --input, streamed through a virtual table.
+fn main_query(options: &Options, sql: &str) -> anyhow::Result<()> {
+    check_standalone(options, "--query")?;
+
+    let filter = record_filter(options)?;
+    let reader = expect_reader(options, filter.clone())?;
+    check_jsonl_output(options, "--query")?;
+
+    let writer = spawn_writer(options);
+    // Not locked, as the writer may be using STDOUT.
+    let mut stdout = BufWriter::new(io::stdout());
+
+    let summary = query::query(&reader, sql, |row| {
+        if let Some((ref write_tx, _)) = writer {
+            // If the writer has stopped, its error is reported when it's joined.
+            Ok(write_tx.send(row).is_ok())
+        } else {
+            match writeln!(stdout, "{}", row) {
+                Err(err) if err.kind() == io::ErrorKind::BrokenPipe => Ok(false),
+                result => Ok(result.map(|_| true)?),
+            }
         }
     });
-    for rec in rx.iter() {
-        if let Some(doi) = get_doi_from_record(&rec) {
-            println!("{}", doi);
+
+    join_writer(writer)?;
+    let summary = summary?;
+
+    match stdout.into_inner() {
+        Ok(_) => (),
+        Err(err) if err.error().kind() == io::ErrorKind::BrokenPipe => (),
+        Err(err) => return Err(err.into_error().into()),
+    }
+
+    report_skipped_files(&summary.skipped);
+    if summary.records_skipped > 0 || summary.files_abandoned > 0 {
+        eprintln!(
+            "Skipped {} records with errors and {} partly unreadable files.",
+            summary.records_skipped, summary.files_abandoned
+        );
+    }
+    if options.verbose {
+        eprintln!("Scans of the input: {}", summary.scans);
+    }
+
+    report_doi_lists(&filter, options.doi_list_not_found.as_deref())?;
+
+    Ok(())
+}
+
+/// Finds the records for a DOI.
+type Lookup = Box<dyn FnMut(&str) -> anyhow::Result<Vec<Value>>>;
+
+/// The output file's writer thread, and the channel to send it records.
+type Writer = (SyncSender<Value>, JoinHandle<anyhow::Result<()>>);
+
+/// Start writing to --output-file, if given.
+/// The writer runs on its own thread so that compression doesn't hold up reading.
+fn spawn_writer(options: &Options) -> Option<Writer> {
+    let write_options = WriteOptions {
+        verbose: options.verbose,
+        compression_level: options.compression_level,
+        row_group_size: options.row_group_size,
+        index: options.index,
+    };
+
+    options.output_file.clone().map(|output_file| {
+        let (write_tx, write_rx): (SyncSender<Value>, Receiver<Value>) =
+            mpsc::sync_channel(CHANNEL_SIZE);
+        let write_thread = thread::spawn(move || {
+            let without_doi = write_chan_to_file(&output_file, write_rx, &write_options)?;
+            if without_doi > 0 {
+                eprintln!(
+                    "Skipped {} records without DOIs, which can't be stored in {:?}",
+                    without_doi, output_file
+                );
+            }
+            Ok(())
+        });
+        (write_tx, write_thread)
+    })
+}
+
+/// Start writing to --output-file, or to STDOUT as JSON Lines if there isn't one.
+/// For commands whose only output is what they write.
+fn spawn_writer_or_stdout(options: &Options) -> Option<Writer> {
+    if options.output_file.is_some() {
+        spawn_writer(options)
+    } else {
+        spawn_writer(&Options {
+            output_file: Some(PathBuf::from(STDIO_PATH)),
+            ..options.clone()
+        })
+    }
+}
+
+/// Close the writer's channel so it can finish, and wait for it.
+fn join_writer(writer: Option<Writer>) -> anyhow::Result<()> {
+    if let Some((write_tx, write_thread)) = writer {
+        drop(write_tx);
+        write_thread
+            .join()
+            .map_err(|err| anyhow::format_err!("Failed to join writer thread: {:?}", err))??;
+    }
+
+    Ok(())
+}
+
+/// Check the --output-file format and options, before spending time reading.
+fn check_output(options: &Options) -> anyhow::Result<()> {
+    let Some(ref output_file) = options.output_file else {
+        if options.index {
+            return Err(anyhow::format_err!("--index needs an --output-file"));
+        }
+        if options.compression_level.is_some() {
+            return Err(anyhow::format_err!(
+                "--compression-level needs a compressed --output-file"
+            ));
+        }
+        return Ok(());
+    };
+
+    let format = OutputFormat::from_path(output_file)?;
+    if format == OutputFormat::Parquet && options.row_group_size == 0 {
+        return Err(anyhow::format_err!("--row-group-size must be at least 1"));
+    }
+
+    match format {
+        OutputFormat::JsonLines(compression) if compression != Compression::None => {
+            compression.check_level(options.compression_level)?;
+        }
+        _ if options.compression_level.is_some() => {
+            return Err(anyhow::format_err!(
+                "--compression-level only applies to compressed JSON Lines output, not {:?}",
+                output_file
+            ));
         }
+        _ => (),
+    }
+
+    // Tabular and database outputs take their columns from the raw record, so can't hold the common schema.
+    if options.normalize && !matches!(format, OutputFormat::JsonLines(_)) {
+        return Err(anyhow::format_err!(
+            "--normalize output must be JSON Lines, not {:?}",
+            output_file
+        ));
+    }
+
+    if options.index
+        && (format != OutputFormat::JsonLines(Compression::Gzip) || is_stdio(output_file))
+    {
+        return Err(anyhow::format_err!(
+            "Only .jsonl.gz output files can be indexed, not {:?}",
+            output_file
+        ));
     }
-    read_thread
-        .join()
-        .unwrap_or_else(|err| eprintln!("Failed to join reader thread: {:?}", err));
+
     Ok(())
 }
 
-fn main_output_file(options: &Options, output_file: &PathBuf) -> Result<(), anyhow::Error> {
-    let verbose = options.verbose;
-    let (input_dir, paths) = expect_input_files(options)?;
-    if output_file.starts_with(&input_dir) {
-        eprint!(
-            "Output file {:?} can't be in the input directory {:?}",
-            output_file, input_dir
+/// Check that --output-file, if given, is JSON Lines, for commands whose output doesn't fit CSV or Parquet.
+fn check_jsonl_output(options: &Options, command: &str) -> anyhow::Result<()> {
+    check_output(options)?;
+
+    if let Some(ref output_file) = options.output_file {
+        match OutputFormat::from_path(output_file)? {
+            OutputFormat::JsonLines(_) => (),
+            _ => {
+                return Err(anyhow::format_err!(
+                    "{} output must be JSON Lines, not {:?}",
+                    command,
+                    output_file
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that none of the options that only apply to plain reading are given with a command
+/// that has its own output, as they'd be ignored.
+fn check_standalone(options: &Options, command: &str) -> anyhow::Result<()> {
+    let ignored: Vec<&str> = [
+        (options.stats, "--stats"),
+        (options.print_dois, "--print-dois"),
+        (options.validate_dois, "--validate-dois"),
+        (options.dedupe, "--dedupe"),
+        (options.r#where.is_some(), "--where"),
+        (options.select.is_some(), "--select"),
+        (options.normalize, "--normalize"),
+    ]
+    .into_iter()
+    .filter_map(|(given, option)| given.then_some(option))
+    .collect();
+
+    if ignored.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::format_err!(
+            "{} can't be combined with {}",
+            command,
+            ignored.join(", ")
+        ))
+    }
+}
+
+/// Check that no record filters are given with a command that doesn't read records through a filter,
+/// as they'd be ignored.
+fn check_unfiltered(options: &Options, command: &str) -> anyhow::Result<()> {
+    let ignored: Vec<&str> = [
+        (!options.filter_prefix.is_empty(), "--filter-prefix"),
+        (options.filter_agency.is_some(), "--filter-agency"),
+        (!options.filter_type.is_empty(), "--filter-type"),
+        (
+            options.filter_published_from.is_some(),
+            "--filter-published-from",
+        ),
+        (
+            options.filter_published_until.is_some(),
+            "--filter-published-until",
+        ),
+        (
+            options.filter_updated_from.is_some(),
+            "--filter-updated-from",
+        ),
+        (
+            options.filter_updated_until.is_some(),
+            "--filter-updated-until",
+        ),
+        (options.doi_list.is_some(), "--doi-list"),
+        (options.exclude_doi_list.is_some(), "--exclude-doi-list"),
+        (options.doi_list_not_found.is_some(), "--doi-list-not-found"),
+    ]
+    .into_iter()
+    .filter_map(|(given, option)| given.then_some(option))
+    .collect();
+
+    if ignored.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow::format_err!(
+            "{} can't be combined with {}",
+            command,
+            ignored.join(", ")
+        ))
+    }
+}
+
+/// Report the number of records and files that couldn't be read, to STDERR.
+fn report_errors(errors: &ErrorLog) {
+    if errors.records_skipped() > 0 || errors.files_abandoned() > 0 {
+        eprintln!(
+            "Skipped {} records with errors and {} partly unreadable files.",
+            errors.records_skipped(),
+            errors.files_abandoned()
         );
-        exit(1);
     }
-    let (tx, rx): (SyncSender<Value>, Receiver<Value>) = mpsc::sync_channel(10);
-    let read_thread = thread::spawn(move || {
-        if let Err(err) = read_paths_to_channel(&paths, tx, verbose) {
-            eprintln!("Failed read archives: {:?}", err);
+}
+
+/// Directory for temporary files, from --temp-dir or the system default.
+fn temp_dir(options: &Options) -> PathBuf {
+    options.temp_dir.clone().unwrap_or_else(std::env::temp_dir)
+}
+
+/// Apply the --where and --select jq expressions to a record, producing zero or more records.
+/// Each output of --select becomes a record, as in jq.
+fn apply_jq(
+    record: Record,
+    where_filter: Option<&JqFilter>,
+    select: Option<&JqFilter>,
+) -> anyhow::Result<Vec<Record>> {
+    if let Some(where_filter) = where_filter {
+        if !where_filter.matches(&record.value)? {
+            return Ok(vec![]);
         }
-    });
-    write_chan_to_json_gz(output_file, rx, verbose)?;
-    read_thread
-        .join()
-        .unwrap_or_else(|err| eprintln!("Failed to join reader thread: {:?}", err));
+    }
+
+    match select {
+        Some(select) => Ok(select
+            .run(&record.value)?
+            .into_iter()
+            .map(|value| Record::new(record.source.clone(), value))
+            .collect()),
+        None => Ok(vec![record]),
+    }
+}
+
+/// Report files found in the input that weren't read, to STDERR.
+fn report_skipped_files(skipped: &[SkippedFile]) {
+    for file in skipped {
+        eprintln!("Skipped {:?}: {}", file.path, file.reason);
+    }
+}
+
+/// Build the record filter from the --filter-* and DOI list options.
+fn record_filter(options: &Options) -> anyhow::Result<RecordFilter> {
+    let load_doi_list = |path: &PathBuf| -> anyhow::Result<Arc<DoiSet>> {
+        let dois = DoiSet::from_file(path)
+            .map_err(|err| err.context(format!("Failed to read DOI list {:?}", path)))?;
+        if options.verbose {
+            eprintln!("Loaded {} DOIs from {:?}", dois.len(), path);
+        }
+        Ok(Arc::new(dois))
+    };
+
+    Ok(RecordFilter {
+        dois: options.doi_list.as_ref().map(load_doi_list).transpose()?,
+        exclude_dois: options
+            .exclude_doi_list
+            .as_ref()
+            .map(load_doi_list)
+            .transpose()?,
+        prefixes: options.filter_prefix.clone(),
+        agency: options.filter_agency,
+        types: options.filter_type.clone(),
+        published: DateRange {
+            from: options.filter_published_from.clone(),
+            until: options.filter_published_until.clone(),
+        },
+        updated: DateRange {
+            from: options.filter_updated_from.clone(),
+            until: options.filter_updated_until.clone(),
+        },
+    })
+}
+
+/// Report DOIs in the DOI lists that weren't found in the input, to STDERR.
+/// Those from --doi-list are written to the --doi-list-not-found file, if given.
+fn report_doi_lists(filter: &RecordFilter, not_found_file: Option<&Path>) -> anyhow::Result<()> {
+    if let Some(ref dois) = filter.dois {
+        eprintln!(
+            "{} of {} DOIs in --doi-list weren't found.",
+            dois.not_found_count(),
+            dois.len()
+        );
+
+        if let Some(not_found_file) = not_found_file {
+            let mut out = BufWriter::new(File::create(not_found_file)?);
+            dois.write_not_found(&mut out)?;
+            out.flush()?;
+        }
+    }
+
+    if let Some(ref dois) = filter.exclude_dois {
+        eprintln!(
+            "{} of {} DOIs in --exclude-doi-list weren't found.",
+            dois.not_found_count(),
+            dois.len()
+        );
+    }
+
     Ok(())
 }
 
-/// Return the input directory and a list of input files recursively found there.
-/// Error if no option supplied.
-fn expect_input_files(options: &Options) -> anyhow::Result<(PathBuf, Vec<PathBuf>)> {
-    if let Some(ref input_dir) = options.input {
-        let files = find_input_files(input_dir)?;
-        Ok((input_dir.clone(), files))
+/// Return a reader for the input, configured from the options.
+/// Error if no input supplied.
+fn expect_reader(options: &Options, filter: RecordFilter) -> anyhow::Result<SnapshotReader> {
+    if let Some(ref input) = options.input {
+        Ok(configure_reader(options, input, filter))
     } else {
         Err(anyhow::format_err!("Please supply <input>"))
     }
 }
 
-/// Return list of relevant files from path. If it's a directory, recurse.
-fn find_input_files(input_path: &std::path::PathBuf) -> anyhow::Result<Vec<PathBuf>> {
-    let mut paths: Vec<PathBuf> = vec![];
-
-    fn r(path: &std::path::PathBuf, paths: &mut Vec<PathBuf>) -> anyhow::Result<()> {
-        if path.is_file() {
-            if let Some(path_str) = path.to_str() {
-                // Crossref public data file torrent is many `.json.gz` files.
-                if path_str.ends_with(".json.gz") ||
-                    // DataCite public data file is one `.tgz` file with many `.jsonl` entries.
-                    path_str.ends_with(".tgz") ||
-                    // Format generated by this tool.
-                    path_str.ends_with(".jsonl.gz")
-                {
-                    paths.push(path.clone());
-                }
-            }
-            Ok(())
-        } else if path.is_dir() {
-            for entry in fs::read_dir(path)? {
-                let path = entry?.path();
-                r(&path, paths)?
+/// Return a reader for an input path, configured from the options.
+fn configure_reader(options: &Options, input: &Path, filter: RecordFilter) -> SnapshotReader {
+    let mut reader = SnapshotReader::new(input)
+        .verbose(options.verbose)
+        .threads(options.threads.unwrap_or(0))
+        .deterministic(options.deterministic)
+        .on_error(options.on_error)
+        .filter(filter);
+
+    if let Some(ref error_log) = options.error_log {
+        reader = reader.error_log(error_log);
+    }
+
+    reader
+}
+
+/// Return an error log for commands that read more than one input, so they share one log file.
+fn shared_error_log(options: &Options) -> anyhow::Result<Arc<ErrorLog>> {
+    Ok(Arc::new(ErrorLog::new(
+        options.on_error,
+        options.error_log.as_deref(),
+        options.verbose,
+    )?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        Options::from_iter(std::iter::once("pardalotus_snapshot_tool").chain(args.iter().copied()))
+    }
+
+    #[test]
+    fn normalize_needs_json_lines_output() {
+        for output in ["out.csv", "out.parquet", "out.sqlite", "out.redb"] {
+            assert!(check_output(&options(&["--normalize", "-o", output])).is_err());
+        }
+
+        assert!(check_output(&options(&["--normalize", "-o", "out.jsonl.gz"])).is_ok());
+        assert!(check_output(&options(&["--normalize", "-o", "-"])).is_ok());
+    }
+
+    #[test]
+    fn compression_level_needs_compressed_output() {
+        for output in [
+            "out.jsonl",
+            "-",
+            "out.csv",
+            "out.parquet",
+            "out.redb",
+            "out.sqlite",
+            "out.db",
+        ] {
+            assert!(
+                check_output(&options(&["--compression-level", "5", "-o", output])).is_err(),
+                "{}",
+                output
+            );
+        }
+        assert!(check_output(&options(&["--compression-level", "5"])).is_err());
+
+        for output in [
+            "out.jsonl.gz",
+            "out.jsonl.zst",
+            "out.jsonl.xz",
+            "out.jsonl.bz2",
+        ] {
+            assert!(check_output(&options(&["--compression-level", "5", "-o", output])).is_ok());
+        }
+        assert!(check_output(&options(&[
+            "--compression-level",
+            "99",
+            "-o",
+            "out.jsonl.gz"
+        ]))
+        .is_err());
+        assert!(check_output(&options(&["-o", "out.csv"])).is_ok());
+    }
+
+    #[test]
+    fn row_group_size_must_be_positive() {
+        let err =
+            check_output(&options(&["-o", "out.parquet", "--row-group-size", "0"])).unwrap_err();
+        assert!(err.to_string().contains("--row-group-size"));
+        assert!(check_output(&options(&["-o", "out.parquet", "--row-group-size", "1"])).is_ok());
+    }
+
+    #[test]
+    fn update_needs_output_file() {
+        let err = main_update(&options(&["--input", "base", "--update", "updates"])).unwrap_err();
+        assert!(err.to_string().contains("--output-file"));
+    }
+
+    #[test]
+    fn commands_reject_ignored_options() {
+        for command in [
+            &["--diff", "new"][..],
+            &["--history"],
+            &["--update", "updates"],
+            &["--query", "SELECT 1"],
+            &["--lookup", "10.1/a"],
+        ] {
+            for extra in [
+                &["--where", ".x"][..],
+                &["--select", "{DOI}"],
+                &["--normalize"],
+                &["--dedupe"],
+            ] {
+                let args: Vec<&str> = command.iter().chain(extra).copied().collect();
+                assert!(check_standalone(&options(&args), command[0]).is_err());
             }
 
-            Ok(())
-        } else {
-            Ok(())
+            assert!(check_standalone(&options(command), command[0]).is_ok());
         }
     }
 
-    r(input_path, &mut paths)?;
+    #[test]
+    fn lookup_rejects_filters() {
+        for extra in [
+            &["--filter-prefix", "10.5555"][..],
+            &["--filter-agency", "crossref"],
+            &["--filter-type", "dataset"],
+            &["--filter-published-from", "2020"],
+            &["--filter-updated-until", "2020-01"],
+            &["--doi-list", "dois.txt"],
+            &["--exclude-doi-list", "dois.txt"],
+        ] {
+            let args: Vec<&str> = ["--lookup", "10.1/a"]
+                .iter()
+                .chain(extra)
+                .copied()
+                .collect();
+            let err = check_unfiltered(&options(&args), "--lookup").unwrap_err();
+            assert!(err.to_string().contains(extra[0]));
+        }
 
-    Ok(paths)
+        assert!(check_unfiltered(&options(&["--lookup", "10.1/a"]), "--lookup").is_ok());
+    }
 }
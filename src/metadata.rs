@@ -1,6 +1,41 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-pub(crate) fn get_doi_from_record(record: &Value) -> Option<String> {
+/// Registration agency that a record came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Agency {
+    Crossref,
+    DataCite,
+}
+
+impl fmt::Display for Agency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Agency::Crossref => write!(f, "crossref"),
+            Agency::DataCite => write!(f, "datacite"),
+        }
+    }
+}
+
+impl FromStr for Agency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "crossref" => Ok(Agency::Crossref),
+            "datacite" => Ok(Agency::DataCite),
+            _ => Err(anyhow::format_err!(
+                "Unrecognised agency {:?}, expected \"crossref\" or \"datacite\"",
+                s
+            )),
+        }
+    }
+}
+
+pub fn get_doi_from_record(record: &Value) -> Option<String> {
     // Crossref DOI
     if let Some(doi) = record.get("DOI").and_then(|doi| doi.as_str()) {
         return Some(String::from(doi));
@@ -11,5 +46,30 @@ pub(crate) fn get_doi_from_record(record: &Value) -> Option<String> {
         return Some(String::from(doi));
     }
 
-    return None;
+    // DataCite DOI from the REST API, with metadata under "attributes".
+    if let Some(doi) = record
+        .get("attributes")
+        .and_then(|attributes| attributes.get("doi"))
+        .and_then(|doi| doi.as_str())
+    {
+        return Some(String::from(doi));
+    }
+
+    None
+}
+
+/// Which agency the record came from, based on the shape of its metadata.
+pub fn get_agency(record: &Value) -> Option<Agency> {
+    if record.get("DOI").is_some() {
+        Some(Agency::Crossref)
+    } else if record.get("doi").is_some()
+        || record
+            .get("attributes")
+            .and_then(|attributes| attributes.get("doi"))
+            .is_some()
+    {
+        Some(Agency::DataCite)
+    } else {
+        None
+    }
 }
@@ -0,0 +1,492 @@
+//! A common shape for records from any agency, so they can be analysed together.
+
+use serde::{Deserialize, Serialize};
+
+use super::{crossref, datacite, TypedRecord};
+use crate::{doi::normalize, metadata::Agency};
+
+/// Metadata normalized to a common shape, whichever agency it came from.
+/// Vocabularies are aligned with DataCite's where the agencies differ, e.g. relation types.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CommonRecord {
+    pub doi: Option<String>,
+
+    pub agency: Agency,
+
+    /// Main title.
+    pub title: Option<String>,
+
+    pub creators: Vec<Creator>,
+
+    pub publisher: Option<String>,
+
+    pub publication_year: Option<i64>,
+
+    /// Crossref type (e.g. "journal-article") or DataCite resourceTypeGeneral (e.g. "Dataset").
+    pub resource_type: Option<String>,
+
+    /// Licence URL.
+    pub license: Option<String>,
+
+    pub related_identifiers: Vec<RelatedIdentifier>,
+}
+
+/// A person or organization responsible for the work.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Creator {
+    /// Full name, or organization name.
+    pub name: Option<String>,
+
+    pub given_name: Option<String>,
+
+    pub family_name: Option<String>,
+
+    /// ORCID iD as a URL, e.g. "https://orcid.org/0000-0002-1825-0097".
+    pub orcid: Option<String>,
+
+    /// ROR ID as a URL, for organizations.
+    pub ror: Option<String>,
+
+    pub affiliations: Vec<Affiliation>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Affiliation {
+    pub name: Option<String>,
+
+    /// ROR ID as a URL, e.g. "https://ror.org/02mhbdp94".
+    pub ror: Option<String>,
+}
+
+/// A link to another resource.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RelatedIdentifier {
+    pub identifier: String,
+
+    /// DataCite identifier type, e.g. "DOI" or "URL".
+    pub identifier_type: Option<String>,
+
+    /// DataCite relation type, e.g. "References" or "IsPreprintOf".
+    pub relation_type: Option<String>,
+}
+
+impl From<&TypedRecord> for CommonRecord {
+    fn from(record: &TypedRecord) -> CommonRecord {
+        match record {
+            TypedRecord::Crossref(work) => CommonRecord::from(work.as_ref()),
+            TypedRecord::DataCite(doi) => CommonRecord::from(doi.as_ref()),
+        }
+    }
+}
+
+impl From<&crossref::Work> for CommonRecord {
+    fn from(work: &crossref::Work) -> CommonRecord {
+        let creators = work
+            .author
+            .iter()
+            .map(|author| Creator {
+                name: author.name.clone().or_else(|| {
+                    // Follow DataCite's "Family, Given" convention.
+                    match (&author.family, &author.given) {
+                        (Some(family), Some(given)) => Some(format!("{}, {}", family, given)),
+                        (Some(family), None) => Some(family.clone()),
+                        (None, given) => given.clone(),
+                    }
+                }),
+                given_name: author.given.clone(),
+                family_name: author.family.clone(),
+                orcid: author.orcid.as_deref().and_then(normalize_orcid),
+                ror: None,
+                affiliations: author
+                    .affiliation
+                    .iter()
+                    .map(|affiliation| Affiliation {
+                        name: affiliation.name.clone(),
+                        ror: affiliation
+                            .id
+                            .iter()
+                            .filter(|id| scheme_is(&id.id_type, "ROR"))
+                            .find_map(|id| id.id.as_deref().and_then(normalize_ror)),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        // Prefer the licence for the version of record.
+        let license = work
+            .license
+            .iter()
+            .find(|license| license.content_version.as_deref() == Some("vor"))
+            .or_else(|| work.license.first())
+            .and_then(|license| license.url.clone());
+
+        let relations = work.relation.iter().flat_map(|(relation_type, relations)| {
+            relations.iter().filter_map(move |relation| {
+                let identifier_type = relation.id_type.as_deref().map(crossref_identifier_type);
+                Some(RelatedIdentifier {
+                    identifier: normalize_identifier(relation.id.as_deref()?, &identifier_type),
+                    identifier_type,
+                    relation_type: Some(crossref_relation_type(relation_type)),
+                })
+            })
+        });
+
+        let references = work.reference.iter().filter_map(|reference| {
+            Some(RelatedIdentifier {
+                identifier: normalize(reference.doi.as_deref()?),
+                identifier_type: Some(String::from("DOI")),
+                relation_type: Some(String::from("References")),
+            })
+        });
+
+        CommonRecord {
+            doi: work.doi.as_deref().map(normalize),
+            agency: Agency::Crossref,
+            title: work.title.first().cloned(),
+            creators,
+            publisher: work.publisher.clone(),
+            publication_year: work
+                .issued
+                .as_ref()
+                .and_then(|date| date.year())
+                .or_else(|| work.published.as_ref().and_then(|date| date.year())),
+            resource_type: work.work_type.clone(),
+            license,
+            related_identifiers: relations.chain(references).collect(),
+        }
+    }
+}
+
+impl From<&datacite::Doi> for CommonRecord {
+    fn from(doi: &datacite::Doi) -> CommonRecord {
+        let creators = doi
+            .creators
+            .iter()
+            .map(|creator| {
+                let identifier = |scheme: &str| {
+                    creator
+                        .name_identifiers
+                        .iter()
+                        .filter(|id| scheme_is(&id.name_identifier_scheme, scheme))
+                        .find_map(|id| id.name_identifier.as_deref())
+                };
+
+                Creator {
+                    name: creator.name.clone(),
+                    given_name: creator.given_name.clone(),
+                    family_name: creator.family_name.clone(),
+                    orcid: identifier("ORCID").and_then(normalize_orcid),
+                    ror: identifier("ROR").and_then(normalize_ror),
+                    affiliations: creator
+                        .affiliation
+                        .iter()
+                        .map(|affiliation| Affiliation {
+                            name: affiliation.name.clone(),
+                            ror: affiliation
+                                .affiliation_identifier
+                                .as_deref()
+                                .filter(|_| {
+                                    scheme_is(&affiliation.affiliation_identifier_scheme, "ROR")
+                                })
+                                .and_then(normalize_ror),
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        // The main title is the one without a type.
+        let title = doi
+            .titles
+            .iter()
+            .find(|title| title.title_type.is_none())
+            .or_else(|| doi.titles.first())
+            .and_then(|title| title.title.clone());
+
+        let related_identifiers = doi
+            .related_identifiers
+            .iter()
+            .filter_map(|related| {
+                Some(RelatedIdentifier {
+                    identifier: normalize_identifier(
+                        related.related_identifier.as_deref()?,
+                        &related.related_identifier_type,
+                    ),
+                    identifier_type: related.related_identifier_type.clone(),
+                    relation_type: related.relation_type.clone(),
+                })
+            })
+            .collect();
+
+        CommonRecord {
+            doi: doi.doi.as_deref().map(normalize),
+            agency: Agency::DataCite,
+            title,
+            creators,
+            publisher: doi.publisher.clone(),
+            publication_year: doi.publication_year,
+            resource_type: doi.types.resource_type_general.clone(),
+            license: doi
+                .rights_list
+                .iter()
+                .find_map(|rights| rights.rights_uri.clone()),
+            related_identifiers,
+        }
+    }
+}
+
+/// DOIs are normalized, other identifiers are left as they are.
+fn normalize_identifier(identifier: &str, identifier_type: &Option<String>) -> String {
+    if scheme_is(identifier_type, "DOI") {
+        normalize(identifier)
+    } else {
+        identifier.to_string()
+    }
+}
+
+fn scheme_is(scheme: &Option<String>, expected: &str) -> bool {
+    scheme
+        .as_deref()
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case(expected))
+}
+
+/// Normalize an ORCID iD in any of its common forms to its canonical URL.
+fn normalize_orcid(orcid: &str) -> Option<String> {
+    // The iD is always the last 19 characters: four groups of four digits (the last may end in X).
+    let orcid = orcid.trim();
+    let id = orcid.get(orcid.len().checked_sub(19)?..)?;
+
+    let valid = id.char_indices().all(|(i, c)| match i {
+        4 | 9 | 14 => c == '-',
+        18 => c.is_ascii_digit() || c == 'X' || c == 'x',
+        _ => c.is_ascii_digit(),
+    });
+
+    valid.then(|| format!("https://orcid.org/{}", id.to_ascii_uppercase()))
+}
+
+/// Normalize a ROR ID, either bare or as a URL, to its canonical URL.
+fn normalize_ror(ror: &str) -> Option<String> {
+    let ror = ror.trim();
+    let id = match ror.rfind("ror.org/") {
+        Some(i) => &ror[i + "ror.org/".len()..],
+        None => ror,
+    };
+
+    (!id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()))
+        .then(|| format!("https://ror.org/{}", id.to_ascii_lowercase()))
+}
+
+/// Convert a Crossref relation type such as "is-preprint-of" to the DataCite equivalent, "IsPreprintOf".
+fn crossref_relation_type(relation_type: &str) -> String {
+    relation_type
+        .split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+/// Convert a Crossref relation identifier type to the DataCite equivalent.
+fn crossref_identifier_type(id_type: &str) -> String {
+    match id_type {
+        "uri" => String::from("URL"),
+        "arxiv" => String::from("arXiv"),
+        "handle" => String::from("Handle"),
+        other => other.to_ascii_uppercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn orcids() {
+        let expected = Some(String::from("https://orcid.org/0000-0002-1825-0097"));
+        assert_eq!(normalize_orcid("0000-0002-1825-0097"), expected);
+        assert_eq!(
+            normalize_orcid("http://orcid.org/0000-0002-1825-0097"),
+            expected
+        );
+        assert_eq!(
+            normalize_orcid(" https://orcid.org/0000-0002-1825-0097 "),
+            expected
+        );
+        assert_eq!(
+            normalize_orcid("0000-0002-1694-233x").as_deref(),
+            Some("https://orcid.org/0000-0002-1694-233X")
+        );
+        assert_eq!(normalize_orcid("0000-0002-1825"), None);
+        assert_eq!(normalize_orcid("0000-0002-1825-009Y"), None);
+        assert_eq!(normalize_orcid("https://orcid.org/"), None);
+    }
+
+    #[test]
+    fn rors() {
+        let expected = Some(String::from("https://ror.org/02mhbdp94"));
+        assert_eq!(normalize_ror("02mhbdp94"), expected);
+        assert_eq!(normalize_ror("https://ror.org/02MHBDP94"), expected);
+        assert_eq!(normalize_ror("ror.org/02mhbdp94"), expected);
+        assert_eq!(normalize_ror("https://ror.org/"), None);
+        assert_eq!(normalize_ror("not a ror"), None);
+    }
+
+    #[test]
+    fn crossref_vocabularies() {
+        assert_eq!(crossref_relation_type("is-preprint-of"), "IsPreprintOf");
+        assert_eq!(crossref_relation_type("references"), "References");
+        assert_eq!(crossref_identifier_type("uri"), "URL");
+        assert_eq!(crossref_identifier_type("doi"), "DOI");
+        assert_eq!(crossref_identifier_type("arxiv"), "arXiv");
+    }
+
+    #[test]
+    fn crossref_to_common() {
+        let record = TypedRecord::from_value(&json!({
+            "DOI": "10.5555/ABC",
+            "type": "journal-article",
+            "title": ["Pardalote nesting", "Alternative"],
+            "publisher": "Example Press",
+            "issued": {"date-parts": [[2020, 5]]},
+            "published": {"date-parts": [[2019]]},
+            "author": [
+                {"given": "Ada", "family": "Lovelace", "ORCID": "http://orcid.org/0000-0002-1825-0097",
+                 "affiliation": [{"name": "Uni", "id": [{"id": "https://ror.org/02mhbdp94", "id-type": "ROR"}]}]},
+                {"family": "Babbage"},
+                {"name": "Consortium"}
+            ],
+            "license": [
+                {"URL": "https://example.org/tdm", "content-version": "tdm"},
+                {"URL": "https://creativecommons.org/licenses/by/4.0/", "content-version": "vor"}
+            ],
+            "relation": {"is-preprint-of": [{"id-type": "doi", "id": "10.5555/PUB"}]},
+            "reference": [{"key": "r1", "DOI": "10.5555/CITED"}, {"key": "r2", "unstructured": "A book"}]
+        }))
+        .unwrap();
+
+        let creator = |name: &str, given: Option<&str>, family: Option<&str>| Creator {
+            name: Some(name.to_string()),
+            given_name: given.map(String::from),
+            family_name: family.map(String::from),
+            ..Creator::default()
+        };
+        let related = |identifier: &str, relation_type: &str| RelatedIdentifier {
+            identifier: identifier.to_string(),
+            identifier_type: Some(String::from("DOI")),
+            relation_type: Some(relation_type.to_string()),
+        };
+
+        assert_eq!(
+            record.to_common(),
+            CommonRecord {
+                doi: Some(String::from("10.5555/abc")),
+                agency: Agency::Crossref,
+                title: Some(String::from("Pardalote nesting")),
+                creators: vec![
+                    Creator {
+                        orcid: Some(String::from("https://orcid.org/0000-0002-1825-0097")),
+                        affiliations: vec![Affiliation {
+                            name: Some(String::from("Uni")),
+                            ror: Some(String::from("https://ror.org/02mhbdp94")),
+                        }],
+                        ..creator("Lovelace, Ada", Some("Ada"), Some("Lovelace"))
+                    },
+                    creator("Babbage", None, Some("Babbage")),
+                    creator("Consortium", None, None),
+                ],
+                publisher: Some(String::from("Example Press")),
+                publication_year: Some(2020),
+                resource_type: Some(String::from("journal-article")),
+                license: Some(String::from("https://creativecommons.org/licenses/by/4.0/")),
+                related_identifiers: vec![
+                    related("10.5555/pub", "IsPreprintOf"),
+                    related("10.5555/cited", "References"),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn datacite_to_common() {
+        let record = TypedRecord::from_value(&json!({"attributes": {
+            "doi": "10.6666/DC1",
+            "titles": [{"title": "Subtitle", "titleType": "Subtitle"}, {"title": "Bird counts"}],
+            "publisher": "Zenodo",
+            "publicationYear": 2021,
+            "types": {"resourceTypeGeneral": "Dataset", "resourceType": "Counts"},
+            "creators": [
+                {"name": "Smith, Jo", "givenName": "Jo", "familyName": "Smith",
+                 "nameIdentifiers": [{"nameIdentifier": "https://orcid.org/0000-0002-1825-0097", "nameIdentifierScheme": "ORCID"}],
+                 "affiliation": [
+                    {"name": "Uni", "affiliationIdentifier": "https://ror.org/02mhbdp94", "affiliationIdentifierScheme": "ROR"},
+                    "Lab"
+                 ]},
+                {"name": "Example Org", "nameType": "Organizational",
+                 "nameIdentifiers": [{"nameIdentifier": "05gq02987", "nameIdentifierScheme": "ROR"}]}
+            ],
+            "rightsList": [{"rights": "No URI"}, {"rightsUri": "https://creativecommons.org/licenses/by/4.0/"}],
+            "relatedIdentifiers": [
+                {"relatedIdentifier": "10.5555/CR1", "relatedIdentifierType": "DOI", "relationType": "References"},
+                {"relatedIdentifier": "https://example.org/X", "relatedIdentifierType": "URL", "relationType": "IsSupplementTo"},
+                {"relationType": "IsPartOf"}
+            ]
+        }}))
+        .unwrap();
+
+        assert_eq!(
+            record.to_common(),
+            CommonRecord {
+                doi: Some(String::from("10.6666/dc1")),
+                agency: Agency::DataCite,
+                title: Some(String::from("Bird counts")),
+                creators: vec![
+                    Creator {
+                        name: Some(String::from("Smith, Jo")),
+                        given_name: Some(String::from("Jo")),
+                        family_name: Some(String::from("Smith")),
+                        orcid: Some(String::from("https://orcid.org/0000-0002-1825-0097")),
+                        ror: None,
+                        affiliations: vec![
+                            Affiliation {
+                                name: Some(String::from("Uni")),
+                                ror: Some(String::from("https://ror.org/02mhbdp94")),
+                            },
+                            Affiliation {
+                                name: Some(String::from("Lab")),
+                                ror: None,
+                            },
+                        ],
+                    },
+                    Creator {
+                        name: Some(String::from("Example Org")),
+                        ror: Some(String::from("https://ror.org/05gq02987")),
+                        ..Creator::default()
+                    },
+                ],
+                publisher: Some(String::from("Zenodo")),
+                publication_year: Some(2021),
+                resource_type: Some(String::from("Dataset")),
+                license: Some(String::from("https://creativecommons.org/licenses/by/4.0/")),
+                related_identifiers: vec![
+                    RelatedIdentifier {
+                        identifier: String::from("10.5555/cr1"),
+                        identifier_type: Some(String::from("DOI")),
+                        relation_type: Some(String::from("References")),
+                    },
+                    RelatedIdentifier {
+                        identifier: String::from("https://example.org/X"),
+                        identifier_type: Some(String::from("URL")),
+                        relation_type: Some(String::from("IsSupplementTo")),
+                    },
+                ],
+            }
+        );
+    }
+}
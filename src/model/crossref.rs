@@ -0,0 +1,305 @@
+//! Crossref works, as found in the Crossref public data file and REST API.
+//! See <https://api.crossref.org/swagger-ui/index.html> for the schema.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{lenient, lenient_integer, lenient_vec};
+
+/// A Crossref work.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Work {
+    #[serde(rename = "DOI", deserialize_with = "lenient")]
+    pub doi: Option<String>,
+
+    /// Work type, e.g. "journal-article".
+    #[serde(rename = "type", deserialize_with = "lenient")]
+    pub work_type: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub title: Vec<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub subtitle: Vec<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub container_title: Vec<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub publisher: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub author: Vec<Contributor>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub editor: Vec<Contributor>,
+
+    /// Earliest known publication date.
+    #[serde(deserialize_with = "lenient")]
+    pub issued: Option<PartialDate>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub published: Option<PartialDate>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub published_print: Option<PartialDate>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub published_online: Option<PartialDate>,
+
+    /// When the DOI was first registered.
+    #[serde(deserialize_with = "lenient")]
+    pub created: Option<PartialDate>,
+
+    /// When the metadata was last updated by the member.
+    #[serde(deserialize_with = "lenient")]
+    pub deposited: Option<PartialDate>,
+
+    /// When the metadata was last processed by Crossref.
+    #[serde(deserialize_with = "lenient")]
+    pub indexed: Option<PartialDate>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub license: Vec<License>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub reference: Vec<Reference>,
+
+    #[serde(deserialize_with = "lenient_integer")]
+    pub reference_count: Option<i64>,
+
+    #[serde(deserialize_with = "lenient_integer")]
+    pub is_referenced_by_count: Option<i64>,
+
+    /// Relations to other works, keyed by relation type, e.g. "is-preprint-of".
+    #[serde(deserialize_with = "lenient_relations")]
+    pub relation: BTreeMap<String, Vec<Relation>>,
+
+    #[serde(rename = "ISSN", deserialize_with = "lenient_vec")]
+    pub issn: Vec<String>,
+
+    #[serde(rename = "ISBN", deserialize_with = "lenient_vec")]
+    pub isbn: Vec<String>,
+
+    #[serde(rename = "URL", deserialize_with = "lenient")]
+    pub url: Option<String>,
+
+    /// Abstract, usually in JATS XML.
+    #[serde(rename = "abstract", deserialize_with = "lenient")]
+    pub abstract_text: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub funder: Vec<Funder>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub subject: Vec<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub language: Option<String>,
+
+    /// Crossref member ID.
+    #[serde(deserialize_with = "lenient")]
+    pub member: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub prefix: Option<String>,
+}
+
+impl Work {
+    /// Parse a work from JSON. Never fails, as unexpected fields are left empty.
+    pub fn from_value(value: &Value) -> Work {
+        Work::deserialize(value).unwrap_or_default()
+    }
+}
+
+/// An author or editor.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Contributor {
+    #[serde(deserialize_with = "lenient")]
+    pub given: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub family: Option<String>,
+
+    /// Name of an organization, for contributors that aren't people.
+    #[serde(deserialize_with = "lenient")]
+    pub name: Option<String>,
+
+    /// ORCID iD URL.
+    #[serde(rename = "ORCID", deserialize_with = "lenient")]
+    pub orcid: Option<String>,
+
+    /// "first" or "additional".
+    #[serde(deserialize_with = "lenient")]
+    pub sequence: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub affiliation: Vec<Affiliation>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Affiliation {
+    #[serde(deserialize_with = "lenient")]
+    pub name: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub id: Vec<AffiliationId>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct AffiliationId {
+    #[serde(deserialize_with = "lenient")]
+    pub id: Option<String>,
+
+    /// Identifier scheme, e.g. "ROR".
+    #[serde(deserialize_with = "lenient")]
+    pub id_type: Option<String>,
+}
+
+/// A date which may only be known to the year or month.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct PartialDate {
+    /// Crossref represents a date as a list containing one list of [year, month, day], of which month and day are optional.
+    #[serde(deserialize_with = "lenient")]
+    pub date_parts: Vec<Vec<Option<i64>>>,
+
+    /// Full timestamp in ISO 8601 format, for dates that have one.
+    #[serde(deserialize_with = "lenient")]
+    pub date_time: Option<String>,
+
+    /// Milliseconds since the Unix epoch, for dates that have one.
+    #[serde(deserialize_with = "lenient_integer")]
+    pub timestamp: Option<i64>,
+}
+
+impl PartialDate {
+    fn parts(&self) -> impl Iterator<Item = i64> + '_ {
+        self.date_parts
+            .first()
+            .into_iter()
+            .flat_map(|parts| parts.iter().map_while(|part| *part))
+    }
+
+    pub fn year(&self) -> Option<i64> {
+        self.parts().next()
+    }
+
+    /// Format as ISO 8601 to the available precision, e.g. "2024", "2024-05" or "2024-05-01".
+    pub fn to_iso8601(&self) -> Option<String> {
+        let parts: Vec<String> = self
+            .parts()
+            .take(3)
+            .enumerate()
+            .map(|(i, part)| {
+                if i == 0 {
+                    format!("{:04}", part)
+                } else {
+                    format!("{:02}", part)
+                }
+            })
+            .collect();
+
+        (!parts.is_empty()).then(|| parts.join("-"))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct License {
+    #[serde(rename = "URL", deserialize_with = "lenient")]
+    pub url: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub start: Option<PartialDate>,
+
+    /// Which version the licence applies to, e.g. "vor" or "am".
+    #[serde(deserialize_with = "lenient")]
+    pub content_version: Option<String>,
+
+    #[serde(deserialize_with = "lenient_integer")]
+    pub delay_in_days: Option<i64>,
+}
+
+/// A reference from this work to another.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Reference {
+    #[serde(deserialize_with = "lenient")]
+    pub key: Option<String>,
+
+    #[serde(rename = "DOI", deserialize_with = "lenient")]
+    pub doi: Option<String>,
+
+    /// Whether the DOI was supplied by the "publisher" or matched by "crossref".
+    #[serde(deserialize_with = "lenient")]
+    pub doi_asserted_by: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub unstructured: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub article_title: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub journal_title: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub author: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub year: Option<String>,
+}
+
+/// A relation to another work or object.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Relation {
+    /// Identifier type, e.g. "doi".
+    #[serde(deserialize_with = "lenient")]
+    pub id_type: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub id: Option<String>,
+
+    /// "subject" or "object".
+    #[serde(deserialize_with = "lenient")]
+    pub asserted_by: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Funder {
+    #[serde(rename = "DOI", deserialize_with = "lenient")]
+    pub doi: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub name: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub award: Vec<String>,
+}
+
+/// Relations are keyed by type. Each may be a single relation or a list of them.
+fn lenient_relations<'de, D>(deserializer: D) -> Result<BTreeMap<String, Vec<Relation>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(match value {
+        Value::Object(relations) => relations
+            .into_iter()
+            .map(|(relation_type, relations)| {
+                let relations = lenient_vec(relations).unwrap_or_default();
+                (relation_type, relations)
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    })
+}
@@ -0,0 +1,321 @@
+//! DataCite DOIs, as found in the DataCite public data file and REST API.
+//! See <https://schema.datacite.org/> for the schema.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::{lenient, lenient_integer, lenient_vec};
+
+/// A DataCite DOI's metadata.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Doi {
+    #[serde(deserialize_with = "lenient")]
+    pub doi: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub prefix: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub suffix: Option<String>,
+
+    /// Other identifiers for the resource.
+    #[serde(deserialize_with = "lenient_vec")]
+    pub identifiers: Vec<Identifier>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub creators: Vec<Creator>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub contributors: Vec<Creator>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub titles: Vec<Title>,
+
+    /// Schema 4.5 allows the publisher to be an object, earlier versions a string. Only the name is kept.
+    #[serde(deserialize_with = "lenient_publisher")]
+    pub publisher: Option<String>,
+
+    /// Often represented as a string.
+    #[serde(deserialize_with = "lenient_integer")]
+    pub publication_year: Option<i64>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub types: Types,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub dates: Vec<Date>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub related_identifiers: Vec<RelatedIdentifier>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub rights_list: Vec<Rights>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub descriptions: Vec<Description>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub subjects: Vec<Subject>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub language: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub version: Option<String>,
+
+    /// Landing page URL.
+    #[serde(deserialize_with = "lenient")]
+    pub url: Option<String>,
+
+    /// "findable", "registered" or "draft".
+    #[serde(deserialize_with = "lenient")]
+    pub state: Option<String>,
+
+    /// When the DOI was created, ISO 8601.
+    #[serde(deserialize_with = "lenient")]
+    pub created: Option<String>,
+
+    /// When the DOI was registered, ISO 8601.
+    #[serde(deserialize_with = "lenient")]
+    pub registered: Option<String>,
+
+    /// When the metadata was last updated, ISO 8601.
+    #[serde(deserialize_with = "lenient")]
+    pub updated: Option<String>,
+}
+
+impl Doi {
+    /// Parse a DOI's metadata from JSON. Never fails, as unexpected fields are left empty.
+    /// Accepts either the bare attributes, or a JSON:API resource as returned by the REST API,
+    /// which has the metadata under "attributes".
+    pub fn from_value(value: &Value) -> Doi {
+        let attributes = value
+            .get("attributes")
+            .filter(|attributes| attributes.is_object())
+            .unwrap_or(value);
+
+        Doi::deserialize(attributes).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Identifier {
+    #[serde(deserialize_with = "lenient")]
+    pub identifier: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub identifier_type: Option<String>,
+}
+
+/// A creator or contributor.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Creator {
+    #[serde(deserialize_with = "lenient")]
+    pub name: Option<String>,
+
+    /// "Personal" or "Organizational".
+    #[serde(deserialize_with = "lenient")]
+    pub name_type: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub given_name: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub family_name: Option<String>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub name_identifiers: Vec<NameIdentifier>,
+
+    #[serde(deserialize_with = "lenient_vec")]
+    pub affiliation: Vec<Affiliation>,
+
+    /// Only for contributors, e.g. "Editor".
+    #[serde(deserialize_with = "lenient")]
+    pub contributor_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct NameIdentifier {
+    #[serde(deserialize_with = "lenient")]
+    pub name_identifier: Option<String>,
+
+    /// E.g. "ORCID" or "ROR".
+    #[serde(deserialize_with = "lenient")]
+    pub name_identifier_scheme: Option<String>,
+
+    #[serde(rename = "schemeUri", deserialize_with = "lenient")]
+    pub scheme_uri: Option<String>,
+}
+
+/// An affiliation. Older records have these as plain strings.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", from = "AffiliationRepr")]
+pub struct Affiliation {
+    pub name: Option<String>,
+
+    pub affiliation_identifier: Option<String>,
+
+    /// E.g. "ROR".
+    pub affiliation_identifier_scheme: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AffiliationRepr {
+    Name(String),
+
+    #[serde(rename_all = "camelCase")]
+    Full {
+        #[serde(default, deserialize_with = "lenient")]
+        name: Option<String>,
+
+        #[serde(default, deserialize_with = "lenient")]
+        affiliation_identifier: Option<String>,
+
+        #[serde(default, deserialize_with = "lenient")]
+        affiliation_identifier_scheme: Option<String>,
+    },
+}
+
+impl From<AffiliationRepr> for Affiliation {
+    fn from(repr: AffiliationRepr) -> Affiliation {
+        match repr {
+            AffiliationRepr::Name(name) => Affiliation {
+                name: Some(name),
+                ..Affiliation::default()
+            },
+            AffiliationRepr::Full {
+                name,
+                affiliation_identifier,
+                affiliation_identifier_scheme,
+            } => Affiliation {
+                name,
+                affiliation_identifier,
+                affiliation_identifier_scheme,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Title {
+    #[serde(deserialize_with = "lenient")]
+    pub title: Option<String>,
+
+    /// None for the main title, otherwise e.g. "Subtitle" or "TranslatedTitle".
+    #[serde(deserialize_with = "lenient")]
+    pub title_type: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub lang: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Types {
+    /// Controlled vocabulary, e.g. "Dataset" or "Software".
+    #[serde(deserialize_with = "lenient")]
+    pub resource_type_general: Option<String>,
+
+    /// Free text.
+    #[serde(deserialize_with = "lenient")]
+    pub resource_type: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub schema_org: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub citeproc: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub bibtex: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub ris: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Date {
+    /// ISO 8601 date, or a range separated by "/".
+    #[serde(deserialize_with = "lenient")]
+    pub date: Option<String>,
+
+    /// E.g. "Issued", "Created" or "Updated".
+    #[serde(deserialize_with = "lenient")]
+    pub date_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RelatedIdentifier {
+    #[serde(deserialize_with = "lenient")]
+    pub related_identifier: Option<String>,
+
+    /// E.g. "DOI" or "URL".
+    #[serde(deserialize_with = "lenient")]
+    pub related_identifier_type: Option<String>,
+
+    /// E.g. "IsSupplementTo" or "References".
+    #[serde(deserialize_with = "lenient")]
+    pub relation_type: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub resource_type_general: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Rights {
+    #[serde(deserialize_with = "lenient")]
+    pub rights: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub rights_uri: Option<String>,
+
+    /// SPDX identifier, e.g. "cc-by-4.0".
+    #[serde(deserialize_with = "lenient")]
+    pub rights_identifier: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Description {
+    #[serde(deserialize_with = "lenient")]
+    pub description: Option<String>,
+
+    /// E.g. "Abstract" or "Methods".
+    #[serde(deserialize_with = "lenient")]
+    pub description_type: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Subject {
+    #[serde(deserialize_with = "lenient")]
+    pub subject: Option<String>,
+
+    #[serde(deserialize_with = "lenient")]
+    pub subject_scheme: Option<String>,
+}
+
+/// The publisher may be a string, or an object with a "name".
+fn lenient_publisher<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(match value {
+        Value::String(name) => Some(name),
+        Value::Object(publisher) => publisher
+            .get("name")
+            .and_then(|name| name.as_str())
+            .map(String::from),
+        _ => None,
+    })
+}
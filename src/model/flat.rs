@@ -0,0 +1,95 @@
+//! Core fields flattened into a single row, for tabular outputs.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{common::CommonRecord, TypedRecord};
+use crate::metadata::get_doi_from_record;
+
+/// One row per record: core fields from either agency, plus the raw JSON.
+/// Fields are None if the record doesn't have them, or isn't from a recognised agency.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FlatRecord {
+    pub doi: Option<String>,
+
+    /// "crossref" or "datacite".
+    pub agency: Option<String>,
+
+    /// Crossref type (e.g. "journal-article") or DataCite resourceTypeGeneral (e.g. "Dataset").
+    pub resource_type: Option<String>,
+
+    pub publication_year: Option<i64>,
+
+    pub publisher: Option<String>,
+
+    /// Main title.
+    pub title: Option<String>,
+
+    /// Number of authors or creators.
+    pub creator_count: Option<i64>,
+
+    /// Number of references. For DataCite, the number of related identifiers with the "References" relation.
+    pub reference_count: Option<i64>,
+
+    /// Licence URL.
+    pub license: Option<String>,
+
+    /// When the metadata was last updated: Crossref "deposited" or DataCite "updated", ISO 8601.
+    pub updated: Option<String>,
+
+    /// The record as found in the snapshot.
+    pub json: String,
+}
+
+impl FlatRecord {
+    pub fn from_value(value: &Value) -> FlatRecord {
+        let json = value.to_string();
+
+        let Some(typed) = TypedRecord::from_value(value) else {
+            return FlatRecord {
+                doi: get_doi_from_record(value),
+                json,
+                ..FlatRecord::default()
+            };
+        };
+
+        FlatRecord::from_typed(&typed, &typed.to_common(), json)
+    }
+
+    /// Flatten a record that's already been parsed and normalized.
+    pub fn from_typed(typed: &TypedRecord, common: &CommonRecord, json: String) -> FlatRecord {
+        let (reference_count, updated) = match typed {
+            TypedRecord::Crossref(work) => (
+                work.reference_count.or_else(|| {
+                    (!work.reference.is_empty()).then_some(work.reference.len() as i64)
+                }),
+                work.deposited
+                    .as_ref()
+                    .and_then(|deposited| deposited.date_time.clone()),
+            ),
+            TypedRecord::DataCite(doi) => (
+                Some(
+                    doi.related_identifiers
+                        .iter()
+                        .filter(|related| related.relation_type.as_deref() == Some("References"))
+                        .count() as i64,
+                ),
+                doi.updated.clone(),
+            ),
+        };
+
+        FlatRecord {
+            doi: common.doi.clone(),
+            agency: Some(common.agency.to_string()),
+            resource_type: common.resource_type.clone(),
+            publication_year: common.publication_year,
+            publisher: common.publisher.clone(),
+            title: common.title.clone(),
+            creator_count: Some(common.creators.len() as i64),
+            reference_count,
+            license: common.license.clone(),
+            updated,
+            json,
+        }
+    }
+}
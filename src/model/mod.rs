@@ -0,0 +1,195 @@
+//! Typed models of Crossref and DataCite metadata records.
+//!
+//! Snapshots contain decades of metadata deposited against several schema versions, so fields are
+//! deserialized leniently. A field that is missing or has an unexpected shape becomes `None` or empty,
+//! rather than failing the whole record.
+
+pub mod common;
+pub mod crossref;
+pub mod datacite;
+pub mod flat;
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::metadata::{get_agency, Agency};
+use common::CommonRecord;
+
+/// A record parsed according to the schema of its registration agency.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypedRecord {
+    Crossref(Box<crossref::Work>),
+    DataCite(Box<datacite::Doi>),
+}
+
+impl TypedRecord {
+    /// Parse a record, detecting which agency it came from.
+    /// None if it's not recognisable as either.
+    pub fn from_value(value: &Value) -> Option<TypedRecord> {
+        match get_agency(value)? {
+            Agency::Crossref => Some(TypedRecord::Crossref(Box::new(crossref::Work::from_value(
+                value,
+            )))),
+            Agency::DataCite => Some(TypedRecord::DataCite(Box::new(datacite::Doi::from_value(
+                value,
+            )))),
+        }
+    }
+
+    pub fn agency(&self) -> Agency {
+        match self {
+            TypedRecord::Crossref(_) => Agency::Crossref,
+            TypedRecord::DataCite(_) => Agency::DataCite,
+        }
+    }
+
+    /// Normalize to the common shape shared by all agencies.
+    pub fn to_common(&self) -> CommonRecord {
+        CommonRecord::from(self)
+    }
+
+    pub fn doi(&self) -> Option<&str> {
+        match self {
+            TypedRecord::Crossref(work) => work.doi.as_deref(),
+            TypedRecord::DataCite(doi) => doi.doi.as_deref(),
+        }
+    }
+}
+
+/// Deserialize any type, falling back to its default if the input has the wrong shape.
+pub(crate) fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(T::deserialize(value).unwrap_or_default())
+}
+
+/// Deserialize a list, dropping any items with the wrong shape.
+/// A single item that isn't in a list is treated as a list of one.
+pub(crate) fn lenient_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(match value {
+        Value::Array(items) => items
+            .into_iter()
+            .filter_map(|item| T::deserialize(item).ok())
+            .collect(),
+        Value::Null => vec![],
+        item => T::deserialize(item).into_iter().collect(),
+    })
+}
+
+/// Deserialize an integer that may be represented as a number or a string.
+pub(crate) fn lenient_integer<'de, D>(deserializer: D) -> Result<Option<i64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Value::deserialize(deserializer)?;
+    Ok(match value {
+        Value::Number(number) => number
+            .as_i64()
+            .or_else(|| number.as_f64().map(|x| x as i64)),
+        Value::String(string) => string.trim().parse().ok(),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Default, Deserialize)]
+    #[serde(default)]
+    struct Lenient {
+        #[serde(deserialize_with = "lenient")]
+        name: Option<String>,
+
+        #[serde(deserialize_with = "lenient_vec")]
+        names: Vec<String>,
+
+        #[serde(deserialize_with = "lenient_integer")]
+        count: Option<i64>,
+    }
+
+    fn parse(value: Value) -> Lenient {
+        Lenient::deserialize(value).unwrap()
+    }
+
+    #[test]
+    fn lenient_falls_back_to_default() {
+        assert_eq!(parse(json!({"name": "x"})).name.as_deref(), Some("x"));
+        assert_eq!(parse(json!({"name": 1})).name, None);
+        assert_eq!(parse(json!({"name": ["x"]})).name, None);
+        assert_eq!(parse(json!({"name": null})).name, None);
+        assert_eq!(parse(json!({})).name, None);
+    }
+
+    #[test]
+    fn lenient_vec_drops_bad_items() {
+        assert_eq!(
+            parse(json!({"names": ["a", 1, "b", null]})).names,
+            vec!["a", "b"]
+        );
+        assert_eq!(parse(json!({"names": "a"})).names, vec!["a"]);
+        assert!(parse(json!({"names": null})).names.is_empty());
+        assert!(parse(json!({"names": {"a": 1}})).names.is_empty());
+    }
+
+    #[test]
+    fn lenient_integer_from_numbers_and_strings() {
+        assert_eq!(parse(json!({"count": 3})).count, Some(3));
+        assert_eq!(parse(json!({"count": 3.7})).count, Some(3));
+        assert_eq!(parse(json!({"count": " 42 "})).count, Some(42));
+        assert_eq!(parse(json!({"count": "many"})).count, None);
+        assert_eq!(parse(json!({"count": [1]})).count, None);
+    }
+
+    #[test]
+    fn wrongly_shaped_records_still_parse() {
+        let Some(TypedRecord::Crossref(work)) = TypedRecord::from_value(&json!({
+            "DOI": "10.5555/a",
+            "title": "Not a list",
+            "author": [{"given": "Ada", "family": 7}, "not an author"],
+            "reference-count": "12",
+            "issued": {"date-parts": [[2020, null]]},
+            "relation": {"is-preprint-of": {"id-type": "doi", "id": "10.5555/b"}}
+        })) else {
+            panic!("Not Crossref");
+        };
+        assert_eq!(work.title, vec!["Not a list"]);
+        assert_eq!(work.author.len(), 1);
+        assert_eq!(work.author[0].family, None);
+        assert_eq!(work.reference_count, Some(12));
+        assert_eq!(work.issued.unwrap().to_iso8601().as_deref(), Some("2020"));
+        assert_eq!(work.relation["is-preprint-of"].len(), 1);
+
+        let Some(TypedRecord::DataCite(doi)) = TypedRecord::from_value(&json!({
+            "id": "10.6666/c",
+            "attributes": {
+                "doi": "10.6666/c",
+                "publisher": {"name": "Zenodo"},
+                "publicationYear": "2021",
+                "creators": [{"name": "Smith", "affiliation": ["Uni", {"name": "Lab", "affiliationIdentifier": 5}]}]
+            }
+        })) else {
+            panic!("Not DataCite");
+        };
+        assert_eq!(doi.publisher.as_deref(), Some("Zenodo"));
+        assert_eq!(doi.publication_year, Some(2021));
+        let affiliations: Vec<Option<&str>> = doi.creators[0]
+            .affiliation
+            .iter()
+            .map(|affiliation| affiliation.name.as_deref())
+            .collect();
+        assert_eq!(affiliations, vec![Some("Uni"), Some("Lab")]);
+
+        assert_eq!(TypedRecord::from_value(&json!({"title": "x"})), None);
+    }
+}
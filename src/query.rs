@@ -0,0 +1,397 @@
+//! Running SQL directly against snapshot files, with an in-memory SQLite database.
+//!
+//! Records are exposed as the virtual table `works`, with the columns in [`WORKS_SCHEMA`].
+//! Nothing is stored: each scan of the table reads the input again, as a stream.
+
+use std::{
+    cell::OnceCell,
+    ffi::c_int,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use rusqlite::{
+    ffi,
+    types::ValueRef,
+    vtab::{
+        read_only_module, Context, CreateVTab, Filters, IndexInfo, VTab, VTabConnection,
+        VTabCursor, VTabKind,
+    },
+    Connection,
+};
+use serde_json::{Map, Value};
+
+use crate::{
+    detect::SkippedFile, is_stdio, model::flat::FlatRecord, read::Records, Record, SnapshotReader,
+};
+
+/// Columns of the `works` table. The same as [`FlatRecord`], plus the file each record was read from.
+/// `type` is a hidden alias of `resource_type`, so it isn't in `SELECT *`.
+pub const WORKS_SCHEMA: &str = "CREATE TABLE works (
+    doi TEXT,
+    agency TEXT,
+    resource_type TEXT,
+    publication_year INTEGER,
+    publisher TEXT,
+    title TEXT,
+    creator_count INTEGER,
+    reference_count INTEGER,
+    license TEXT,
+    updated TEXT,
+    source TEXT,
+    json TEXT,
+    type TEXT HIDDEN
+)";
+
+/// What happened while reading the input, over all scans.
+#[derive(Debug, Default)]
+pub struct QuerySummary {
+    /// Number of times the input was read.
+    pub scans: usize,
+
+    pub records_skipped: usize,
+    pub files_abandoned: usize,
+
+    /// Files found in the input that weren't read, because they weren't recognised.
+    pub skipped: Vec<SkippedFile>,
+}
+
+/// Run a SQL statement over the records from the reader, calling `row` with each result row
+/// as a JSON object keyed by column name. Stops early if `row` returns false.
+pub fn query(
+    reader: &SnapshotReader,
+    sql: &str,
+    mut row: impl FnMut(Value) -> anyhow::Result<bool>,
+) -> anyhow::Result<QuerySummary> {
+    let summary = Arc::new(Mutex::new(QuerySummary::default()));
+
+    // Every scan reports to the same log, so errors are only counted and logged once.
+    let errors = reader.error_log_for_read()?;
+
+    let db = Connection::open_in_memory()?;
+    db.create_module(
+        c"snapshot",
+        read_only_module::<WorksTable>(),
+        Some(Arc::new(Input {
+            reader: reader.clone().errors(errors.clone()),
+            summary: summary.clone(),
+        })),
+    )?;
+    db.execute_batch("CREATE VIRTUAL TABLE temp.works USING snapshot")?;
+
+    {
+        let mut statement = db.prepare(sql)?;
+        let names: Vec<String> = statement
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+
+        let mut rows = statement.query([])?;
+        while let Some(result) = rows.next()? {
+            let mut object = Map::new();
+            for (i, name) in names.iter().enumerate() {
+                object.insert(name.clone(), to_json(result.get_ref(i)?));
+            }
+
+            if !row(Value::Object(object))? {
+                break;
+            }
+        }
+    }
+
+    drop(db);
+    errors.flush()?;
+    let mut summary = Arc::try_unwrap(summary)
+        .map_err(|_| anyhow::format_err!("Query still running"))?
+        .into_inner()
+        .map_err(|_| anyhow::format_err!("Query summary poisoned"))?;
+    summary.records_skipped = errors.records_skipped();
+    summary.files_abandoned = errors.files_abandoned();
+    Ok(summary)
+}
+
+/// Convert a SQLite value to JSON. Blobs are written as hex strings.
+fn to_json(value: ValueRef) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(integer) => Value::from(integer),
+        ValueRef::Real(real) => serde_json::Number::from_f64(real)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        ValueRef::Text(text) => Value::String(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(blob) => Value::String(blob.iter().map(|b| format!("{:02x}", b)).collect()),
+    }
+}
+
+/// The input to read for each scan, and where to count what happened.
+struct Input {
+    reader: SnapshotReader,
+    summary: Arc<Mutex<QuerySummary>>,
+}
+
+impl Input {
+    /// Start a new scan of the input.
+    fn scan(&self) -> anyhow::Result<Records> {
+        let mut summary = self.lock()?;
+        if summary.scans > 0 && is_stdio(self.reader.path()) {
+            return Err(anyhow::format_err!(
+                "STDIN can only be read once, but this query scans the works table more than once"
+            ));
+        }
+        summary.scans += 1;
+
+        let records = self.reader.records()?;
+        summary.skipped = records.skipped().to_vec();
+        Ok(records)
+    }
+
+    fn lock(&self) -> anyhow::Result<MutexGuard<'_, QuerySummary>> {
+        self.summary
+            .lock()
+            .map_err(|_| anyhow::format_err!("Query summary poisoned"))
+    }
+}
+
+#[repr(C)]
+struct WorksTable {
+    /// Base class. Must be first.
+    base: ffi::sqlite3_vtab,
+    input: Arc<Input>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for WorksTable {
+    type Aux = Arc<Input>;
+    type Cursor = WorksCursor;
+
+    fn connect(
+        _db: &mut VTabConnection,
+        aux: Option<&Arc<Input>>,
+        _args: &[&[u8]],
+    ) -> rusqlite::Result<(String, WorksTable)> {
+        let input = aux.ok_or_else(|| module_error("No input for works table"))?;
+        Ok((
+            WORKS_SCHEMA.to_string(),
+            WorksTable {
+                base: ffi::sqlite3_vtab::default(),
+                input: input.clone(),
+            },
+        ))
+    }
+
+    /// Only full scans are possible, so every plan costs the same.
+    fn best_index(&self, info: &mut IndexInfo) -> rusqlite::Result<()> {
+        info.set_estimated_cost(1_000_000.0);
+        Ok(())
+    }
+
+    fn open(&'vtab mut self) -> rusqlite::Result<WorksCursor> {
+        Ok(WorksCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            input: self.input.clone(),
+            records: None,
+            record: None,
+            flat: OnceCell::new(),
+            rowid: 0,
+        })
+    }
+}
+
+impl CreateVTab<'_> for WorksTable {
+    const KIND: VTabKind = VTabKind::Default;
+}
+
+#[repr(C)]
+struct WorksCursor {
+    /// Base class. Must be first.
+    base: ffi::sqlite3_vtab_cursor,
+    input: Arc<Input>,
+    records: Option<Records>,
+
+    /// The current record, or None at the end of the scan.
+    record: Option<Record>,
+
+    /// The current record's columns, only worked out if they're used.
+    flat: OnceCell<FlatRecord>,
+
+    rowid: i64,
+}
+
+unsafe impl VTabCursor for WorksCursor {
+    /// Start a new scan from the beginning of the input.
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        _args: &Filters<'_>,
+    ) -> rusqlite::Result<()> {
+        self.records = Some(self.input.scan().map_err(to_module_error)?);
+        self.rowid = 0;
+        self.next()
+    }
+
+    fn next(&mut self) -> rusqlite::Result<()> {
+        self.flat = OnceCell::new();
+        self.record = match self.records.as_mut().and_then(|records| records.next()) {
+            Some(record) => Some(record.map_err(to_module_error)?),
+            None => None,
+        };
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.record.is_none()
+    }
+
+    fn column(&self, ctx: &mut Context, i: c_int) -> rusqlite::Result<()> {
+        let Some(ref record) = self.record else {
+            return ctx.set_result(&rusqlite::types::Null);
+        };
+
+        // These don't need the record to be parsed.
+        match i {
+            10 => return ctx.set_result(&record.source.to_string_lossy()),
+            11 => return ctx.set_result(&record.value.to_string()),
+            _ => (),
+        }
+
+        let flat = self
+            .flat
+            .get_or_init(|| FlatRecord::from_value(&record.value));
+        match i {
+            0 => ctx.set_result(&flat.doi),
+            1 => ctx.set_result(&flat.agency),
+            2 => ctx.set_result(&flat.resource_type),
+            3 => ctx.set_result(&flat.publication_year),
+            4 => ctx.set_result(&flat.publisher),
+            5 => ctx.set_result(&flat.title),
+            6 => ctx.set_result(&flat.creator_count),
+            7 => ctx.set_result(&flat.reference_count),
+            8 => ctx.set_result(&flat.license),
+            9 => ctx.set_result(&flat.updated),
+            12 => ctx.set_result(&flat.resource_type),
+            _ => Err(module_error(&format!("No column {} in works table", i))),
+        }
+    }
+
+    fn rowid(&self) -> rusqlite::Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+fn module_error(message: &str) -> rusqlite::Error {
+    rusqlite::Error::ModuleError(message.to_string())
+}
+
+fn to_module_error(err: anyhow::Error) -> rusqlite::Error {
+    module_error(&format!("{:#}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use super::*;
+
+    /// Run a query over files in a new temporary directory, returning the rows and summary.
+    fn run(files: &[(&str, &str)], sql: &str) -> (Vec<Value>, QuerySummary) {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+
+        let mut rows = vec![];
+        let summary = query(&SnapshotReader::new(dir.path()), sql, |row| {
+            rows.push(row);
+            Ok(true)
+        })
+        .unwrap();
+        (rows, summary)
+    }
+
+    const RECORDS: &str = r#"{"DOI": "10.1/a", "type": "journal-article"}
+{"DOI": "10.1/b", "type": "book"}
+{"DOI": "10.1/c", "type": "journal-article"}
+{"doi": "10.2/d", "types": {"resourceTypeGeneral": "Dataset"}}
+"#;
+
+    #[test]
+    fn group_by_type() {
+        let (rows, summary) = run(
+            &[("a.jsonl", RECORDS)],
+            "SELECT type, count(*) AS n FROM works GROUP BY type ORDER BY type",
+        );
+        assert_eq!(
+            Value::Array(rows),
+            serde_json::json!([
+                {"type": "Dataset", "n": 1},
+                {"type": "book", "n": 1},
+                {"type": "journal-article", "n": 2},
+            ])
+        );
+        assert_eq!(summary.scans, 1);
+    }
+
+    #[test]
+    fn type_hidden_from_select_star() {
+        let (rows, _) = run(&[("a.jsonl", RECORDS)], "SELECT * FROM works LIMIT 1");
+        let row = rows[0].as_object().unwrap();
+        assert!(!row.contains_key("type"));
+        assert_eq!(row["resource_type"], "journal-article");
+        assert_eq!(row.len(), 12);
+    }
+
+    #[test]
+    fn limit_stops_early_and_counts_errors() {
+        let mut content = String::from("{\"DOI\": \"10.1/first\"}\nnot json\n");
+        for i in 0..1000 {
+            content.push_str(&format!("{{\"DOI\": \"10.1/{}\"}}\n", i));
+        }
+
+        let (rows, summary) = run(&[("a.jsonl", &content)], "SELECT doi FROM works LIMIT 2");
+        assert_eq!(rows.len(), 2);
+
+        // Counted even though the scan never reaches the end.
+        assert_eq!(summary.records_skipped, 1);
+    }
+
+    #[test]
+    fn errors_counted_once_over_scans() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("a.jsonl"),
+            "{\"DOI\": \"10.1/a\"}\nnot json\n{\"DOI\": \"10.1/b\"}\n",
+        )
+        .unwrap();
+        let log = dir.path().join("errors.jsonl");
+
+        // The self-join scans the input twice.
+        let reader = SnapshotReader::new(dir.path().join("a.jsonl")).error_log(&log);
+        let sql = "SELECT count(*) AS n FROM works AS a JOIN works AS b ON a.doi = b.doi";
+        let mut rows = Vec::new();
+        let summary = query(&reader, sql, |row| {
+            rows.push(row);
+            Ok(true)
+        })
+        .unwrap();
+
+        assert_eq!(rows[0]["n"], 2);
+        assert!(summary.scans > 1);
+        assert_eq!(summary.records_skipped, 1);
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn stdin_scanned_once() {
+        let input = Input {
+            reader: SnapshotReader::new(Path::new("-")),
+            summary: Arc::new(Mutex::new(QuerySummary {
+                scans: 1,
+                ..QuerySummary::default()
+            })),
+        };
+
+        let err = input.scan().err().unwrap();
+        assert!(err.to_string().contains("STDIN can only be read once"));
+    }
+}
@@ -1,141 +1,903 @@
-use flate2::read::GzDecoder;
-use rayon::prelude::*;
-use std::path::PathBuf;
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde::de::{DeserializeSeed, Deserializer, Error, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use std::path::{Path, PathBuf};
 use tar::Archive;
 
 use std::{
-    fs::File,
+    cell::Cell,
+    ffi::OsStr,
+    fmt,
+    fs::{self, File},
     io::{self, BufRead, BufReader, Read},
-    sync::mpsc::SyncSender,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Receiver, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
 };
 
 use serde_json::Value;
 
+use crate::{
+    compression::{Compression, Decoder},
+    detect::{Container, InputFile, InputFiles, InputFormat, SkipReason, SkippedFile},
+    errors::{Disconnected, ErrorLog, ErrorPolicy, ReadError},
+    filter::RecordFilter,
+    is_stdio,
+    record::Record,
+    STDIO_PATH,
+};
+
+/// Size of bounded channels between readers and consumers.
+pub const CHANNEL_SIZE: usize = 10;
+
+/// Options controlling how input files are read.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReadOptions {
+    /// Send progress messages to STDERR.
+    pub(crate) verbose: bool,
+
+    /// Number of files to read in parallel. If 0 then one per CPU.
+    pub(crate) threads: usize,
+
+    /// Emit records in the order of input files, and the order of records within each file.
+    pub(crate) deterministic: bool,
+
+    /// Only records that match are sent.
+    pub(crate) filter: Arc<RecordFilter>,
+}
+
+/// Sends records that match the filter to a channel.
+struct RecordSender {
+    tx: SyncSender<Record>,
+    filter: Arc<RecordFilter>,
+}
+
+impl RecordSender {
+    fn new(tx: SyncSender<Record>, options: &ReadOptions) -> RecordSender {
+        RecordSender {
+            tx,
+            filter: options.filter.clone(),
+        }
+    }
+
+    /// Send the record if it matches. Error if the consumer has gone away.
+    fn send(&self, record: Record) -> Result<(), Disconnected> {
+        if self.filter.is_empty() || self.filter.matches(&record.value) {
+            self.tx.send(record).map_err(|_| Disconnected)
+        } else {
+            Ok(())
+        }
+    }
+}
+
 /// Read all entries in all files to the channel. One entry per message.
+/// Files are read in parallel by a pool of worker threads.
+/// The channel is bounded, so workers block when consumers fall behind.
+/// Problems with the input are reported to `errors`, which decides whether to carry on.
 pub(crate) fn read_paths_to_channel(
-    paths: &[PathBuf],
-    tx: SyncSender<Value>,
-    verbose: bool,
+    files: &[InputFile],
+    tx: SyncSender<Record>,
+    options: &ReadOptions,
+    errors: &ErrorLog,
 ) -> anyhow::Result<()> {
-    for path in paths.iter() {
-        // path::ends_with comparison for path doesn't work for sub-path-component chunks.
-        // path::extension only takes the lats extension files so is unsuitbale for `.tar.gz`.
-        if let Some(path_str) = path.to_str() {
-            // Ignore other types.
-            if path_str.ends_with(".tgz") {
-                read_tgz_to_channel(path, &tx, verbose)?;
-            } else if path_str.ends_with(".json.gz") {
-                read_json_gz_to_channel(path, &tx, verbose)?;
-            } else if path_str.ends_with(".jsonl.gz") {
-                read_jsonl_gz_to_channel(path, &tx)?;
+    let result = if options.deterministic {
+        read_paths_to_channel_ordered(files, tx, options, errors)
+    } else {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(options.threads)
+            .build()?;
+
+        pool.install(|| {
+            files
+                .par_iter()
+                .try_for_each(|file| read_path_to_channel(file, &tx, options, errors))
+        })
+    };
+
+    match result {
+        // The consumer stopped early, and will report its own reason why.
+        Err(err) if err.is::<Disconnected>() => Ok(()),
+        result => result,
+    }
+}
+
+/// Read all entries in all files to the channel, preserving the order of files and of records within them.
+/// Files are still read in parallel, each to its own bounded channel. These are drained in turn, so workers
+/// can only get a channel's length ahead of the file currently being sent.
+fn read_paths_to_channel_ordered(
+    files: &[InputFile],
+    tx: SyncSender<Record>,
+    options: &ReadOptions,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    let threads = if options.threads == 0 {
+        thread::available_parallelism().map_or(1, |n| n.get())
+    } else {
+        options.threads
+    };
+
+    let (senders, receivers): (Vec<SyncSender<Record>>, Vec<Receiver<Record>>) = files
+        .iter()
+        .map(|_| mpsc::sync_channel(CHANNEL_SIZE))
+        .unzip();
+
+    // Files are handed out in order, so the earliest unfinished file is always being read.
+    let work = Mutex::new(files.iter().zip(senders).enumerate());
+
+    // Index of the first file that failed, so that the others stop and the error can be returned.
+    let failed_at = AtomicUsize::new(usize::MAX);
+
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| -> anyhow::Result<()> {
+                    let result = (|| loop {
+                        if failed_at.load(Ordering::Relaxed) != usize::MAX {
+                            return Ok(());
+                        }
+
+                        let next = work
+                            .lock()
+                            .map_err(|_| anyhow::format_err!("Work queue poisoned"))?
+                            .next();
+
+                        match next {
+                            Some((i, (file, file_tx))) => {
+                                // Recorded before the file's channel closes, so nothing after it is sent.
+                                if let Err(err) =
+                                    read_path_to_channel(file, &file_tx, options, errors)
+                                {
+                                    failed_at.fetch_min(i, Ordering::Relaxed);
+                                    return Err(err);
+                                }
+                            }
+                            None => return Ok(()),
+                        }
+                    })();
+
+                    if result.is_err() {
+                        // Drop the senders for files not yet started, so their receivers disconnect
+                        // rather than being waited on forever.
+                        let mut work = work.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                        work.by_ref().for_each(drop);
+                    }
+                    result
+                })
+            })
+            .collect();
+
+        // Records from files before one that failed are all sent, then the error is returned.
+        'files: for (i, rx) in receivers.into_iter().enumerate() {
+            if i > failed_at.load(Ordering::Relaxed) {
+                break;
+            }
+
+            for record in rx {
+                if tx.send(record).is_err() {
+                    break 'files;
+                }
             }
         }
-    }
 
-    Ok(())
+        // Any receivers not yet drained have now been dropped, so blocked workers will stop.
+        // Workers stopped that way fail with `Disconnected`, so prefer the error that caused it.
+        let mut results = vec![];
+        for worker in workers {
+            results.push(
+                worker
+                    .join()
+                    .map_err(|err| anyhow::format_err!("Failed to join reader thread: {:?}", err))
+                    .and_then(|r| r),
+            );
+        }
+        results.sort_by_key(|result| match result {
+            Ok(()) => 2,
+            Err(err) if err.is::<Disconnected>() => 1,
+            Err(_) => 0,
+        });
+        results.into_iter().next().unwrap_or(Ok(()))
+    })
 }
 
-/// Read gzipped jsonl (JSON Lines) from a file to a channel, one string per line.
-/// This format is generated by this tool.
-fn read_jsonl_gz_to_channel(path: &PathBuf, channel: &SyncSender<Value>) -> anyhow::Result<()> {
-    let f = File::open(path)?;
+/// Read all entries in one file to the channel, dispatching on its type.
+/// If the file can't be read to the end, that's reported as an error against the whole file.
+fn read_path_to_channel(
+    file: &InputFile,
+    tx: &SyncSender<Record>,
+    options: &ReadOptions,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    // Shared by all records from this file.
+    let source: Arc<Path> = Arc::from(file.path.as_path());
+    let sender = RecordSender::new(tx.clone(), options);
+    let result = open_decoded(&file.path, file.format.compression).and_then(|reader| {
+        read_stream_to_channel(
+            reader,
+            file.format.container,
+            &source,
+            &sender,
+            options,
+            errors,
+        )
+    });
 
-    let decoded = BufReader::new(GzDecoder::new(f));
+    check_file_result(&file.path, result, errors)
+}
 
-    decoded.lines().par_bridge().for_each(|line| {
-        if let Ok(l) = line {
-            if let Ok(parsed) = serde_json::from_str::<Value>(&l) {
-                let _ = channel.send(parsed);
+/// Read all entries from STDIN to the channel, recognising its format from the content as for files.
+/// Records have the source `-`.
+fn read_stdin_to_channel(
+    tx: SyncSender<Record>,
+    options: &ReadOptions,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    let source: Arc<Path> = Arc::from(Path::new(STDIO_PATH));
+    let sender = RecordSender::new(tx, options);
+
+    let result = match InputFormat::sniff(io::stdin()) {
+        Ok((format, reader)) => {
+            if options.verbose {
+                eprintln!("Reading {:?} from STDIN", format);
             }
+            read_stream_to_channel(reader, format.container, &source, &sender, options, errors)
         }
-    });
+        // Nothing to read.
+        Err(SkipReason::Empty) => Ok(()),
+        Err(reason) => Err(anyhow::format_err!("STDIN {}", reason)),
+    };
 
-    Ok(())
+    match check_file_result(&source, result, errors) {
+        // The consumer stopped early, and will report its own reason why.
+        Err(err) if err.is::<Disconnected>() => Ok(()),
+        result => result,
+    }
+}
+
+/// Report an error that stopped a file being read to the end.
+fn check_file_result(
+    path: &Path,
+    result: anyhow::Result<()>,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    match result {
+        // Already reported, or not a problem with this file.
+        Err(err) if err.is::<ReadError>() || err.is::<Disconnected>() => Err(err),
+        Err(err) => errors.report(ReadError::file(path, &err)),
+        Ok(()) => Ok(()),
+    }
 }
 
-/// Read a gzipped JSON file.
+/// Read all entries from a decompressed stream to the channel, according to its container.
+fn read_stream_to_channel<R: BufRead + Send>(
+    reader: R,
+    container: Container,
+    source: &Arc<Path>,
+    tx: &RecordSender,
+    options: &ReadOptions,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    match container {
+        Container::Tar => read_tar_to_channel(reader, source, tx, options.verbose, errors),
+        Container::CrossrefJson => read_json_to_channel(reader, source, tx, options.verbose),
+        Container::JsonLines => {
+            read_jsonl_stream_to_channel(reader, source, tx, options.deterministic, errors)
+        }
+    }
+}
+
+/// Open a file and decompress it.
+fn open_decoded(path: &Path, compression: Compression) -> anyhow::Result<BufReader<Decoder<File>>> {
+    let f = File::open(path)?;
+    Ok(BufReader::new(compression.decoder(f)?))
+}
+
+/// Read jsonl (JSON Lines) from a decompressed stream to a channel, one string per line.
+/// This format is generated by this tool.
+/// Lines are parsed in parallel unless `deterministic`, in which case they are sent in order.
+fn read_jsonl_stream_to_channel<R: BufRead + Send>(
+    reader: R,
+    path: &Arc<Path>,
+    channel: &RecordSender,
+    deterministic: bool,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    let send_line = |(i, line): (usize, io::Result<String>)| {
+        send_jsonl_line(line, i + 1, channel, path, None, errors)
+    };
+
+    if deterministic {
+        reader.lines().enumerate().try_for_each(send_line)
+    } else {
+        reader
+            .lines()
+            .enumerate()
+            .par_bridge()
+            .try_for_each(send_line)
+    }
+}
+
+/// Read a decompressed JSON file.
 /// This is expected to be a Crossref file.
-fn read_json_gz_to_channel(
-    path: &PathBuf,
-    tx: &SyncSender<Value>,
+/// The file is stream-parsed so that only one item is held in memory at a time.
+fn read_json_to_channel<R: BufRead>(
+    json: R,
+    path: &Arc<Path>,
+    tx: &RecordSender,
     verbose: bool,
 ) -> anyhow::Result<()> {
     if verbose {
-        eprintln!("Reading .json.gz {:?}", &path);
+        eprintln!("Reading JSON {:?}", &path);
     }
 
-    let f = File::open(path)?;
+    let mut deserializer = serde_json::Deserializer::from_reader(json);
+    let disconnected = Cell::new(false);
 
-    let json = BufReader::new(GzDecoder::new(f));
-    let deserialized: Value = serde_json::from_reader(json)?;
+    let found_items = deserializer
+        .deserialize_map(CrossrefFileVisitor {
+            tx,
+            source: path,
+            disconnected: &disconnected,
+        })
+        .and_then(|found_items| deserializer.end().map(|_| found_items));
 
-    // Crossref files have a top-level key "items" containing items in that snapshot.
-    if let Some(items) = deserialized.get("items").and_then(|x| x.as_array()) {
-        for item in items {
-            // We're splitting the document into parts, so need to make a copy of this subtree.
-            tx.send(item.clone())?;
-        }
-    } else {
-        eprint!("Didn't get recognised JSON format from {:?}", path);
+    if disconnected.get() {
+        return Err(Disconnected.into());
+    }
+
+    if !found_items? {
+        return Err(anyhow::format_err!(
+            "Didn't get recognised JSON format, expected top-level \"items\""
+        ));
     }
 
     if verbose {
-        eprintln!("Finished reading .json.gz {:?}", &path);
+        eprintln!("Finished reading JSON {:?}", &path);
     }
 
     Ok(())
 }
 
-/// Read all entries in all files in a gzipped tar file to a channel.
-fn read_tgz_to_channel(
-    path: &PathBuf,
-    channel: &SyncSender<Value>,
+/// Visits the top-level object of a Crossref file.
+/// Crossref files have a top-level key "items" containing items in that snapshot.
+/// Other keys are skipped without being retained.
+/// Produces true if the "items" key was found.
+struct CrossrefFileVisitor<'a> {
+    tx: &'a RecordSender,
+    source: &'a Arc<Path>,
+
+    /// Set if the channel was closed, as that can only be reported as a deserialization error.
+    disconnected: &'a Cell<bool>,
+}
+
+impl<'de> Visitor<'de> for CrossrefFileVisitor<'_> {
+    type Value = bool;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a Crossref JSON object with an \"items\" array")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut found_items = false;
+
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "items" {
+                map.next_value_seed(CrossrefItemsVisitor {
+                    tx: self.tx,
+                    source: self.source,
+                    disconnected: self.disconnected,
+                })?;
+                found_items = true;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+
+        Ok(found_items)
+    }
+}
+
+/// Visits the "items" array of a Crossref file, sending each item to the channel as soon as it's parsed.
+struct CrossrefItemsVisitor<'a> {
+    tx: &'a RecordSender,
+    source: &'a Arc<Path>,
+    disconnected: &'a Cell<bool>,
+}
+
+impl<'de> DeserializeSeed<'de> for CrossrefItemsVisitor<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for CrossrefItemsVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of Crossref items")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while let Some(item) = seq.next_element::<Value>()? {
+            if self
+                .tx
+                .send(Record::new(self.source.clone(), item))
+                .is_err()
+            {
+                self.disconnected.set(true);
+                return Err(A::Error::custom(Disconnected));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Read all entries in all files in a decompressed tar file to a channel.
+fn read_tar_to_channel<R: Read>(
+    tar: R,
+    path: &Arc<Path>,
+    channel: &RecordSender,
     verbose: bool,
+    errors: &ErrorLog,
 ) -> anyhow::Result<()> {
-    let tar_gz = File::open(path)?;
-    let tar = BufReader::new(GzDecoder::new(tar_gz));
-
     let mut archive = Archive::new(tar);
 
     if verbose {
-        eprintln!("Read TGZ {:?}", path);
+        eprintln!("Read tar {:?}", path);
     }
 
     for entry in archive.entries()? {
         let mut ok_entry = entry?;
-        let entry_path = ok_entry.path()?;
-
-        if entry_path
-            .file_name()
-            .and_then(|x| x.to_str())
-            .map(|x| x.ends_with(".jsonl"))
-            .unwrap_or(false)
-        {
+        let entry_path = ok_entry.path()?.to_path_buf();
+
+        if entry_path.extension() == Some(OsStr::new("jsonl")) {
             if verbose {
-                eprintln!("From TGZ {:?} read {:?}", path, entry_path);
+                eprintln!("From tar {:?} read {:?}", path, entry_path);
             }
 
-            read_jsonl_to_channel(&mut ok_entry, channel)?;
+            read_jsonl_to_channel(&mut ok_entry, channel, path, &entry_path, errors)?;
         }
     }
 
     if verbose {
-        eprintln!("Finished reading TGZ {:?}", path);
+        eprintln!("Finished reading tar {:?}", path);
     }
 
     Ok(())
 }
 
 /// Read a jsonl (JSON Lines) reader to a channel, one string per line.
-/// These are expected to be found in DataCite snapshots.
-fn read_jsonl_to_channel(reader: &mut dyn Read, channel: &SyncSender<Value>) -> anyhow::Result<()> {
+/// These are expected to be found in DataCite snapshots, in the tar `entry` of the file at `path`.
+fn read_jsonl_to_channel(
+    reader: &mut dyn Read,
+    channel: &RecordSender,
+    path: &Arc<Path>,
+    entry: &Path,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
     let reader = io::BufReader::new(reader);
 
-    for line in reader.lines() {
-        let parsed: Value = serde_json::from_str(&line?)?;
-
-        channel.send(parsed)?;
+    for (i, line) in reader.lines().enumerate() {
+        send_jsonl_line(line, i + 1, channel, path, Some(entry), errors)?;
     }
 
     Ok(())
 }
+
+/// Parse one line of JSON Lines and send it to the channel.
+/// A line that can't be parsed is reported, and skipped if the error policy allows.
+/// A line that can't be read means the rest of the file can't be either, so that's returned as an error.
+fn send_jsonl_line(
+    line: io::Result<String>,
+    line_number: usize,
+    channel: &RecordSender,
+    path: &Arc<Path>,
+    entry: Option<&Path>,
+    errors: &ErrorLog,
+) -> anyhow::Result<()> {
+    let line = line?;
+
+    match serde_json::from_str::<Value>(&line) {
+        Ok(parsed) => channel
+            .send(Record::new(path.clone(), parsed))
+            .map_err(|_| Disconnected.into()),
+        Err(err) => errors.report(ReadError::line(path, entry, line_number, &err)),
+    }
+}
+
+/// Reads all metadata records from a snapshot file, or from all snapshot files in a directory.
+///
+/// ```no_run
+/// use pardalotus_snapshot_tool::SnapshotReader;
+///
+/// for record in SnapshotReader::new("/path/to/snapshots").threads(4).records()? {
+///     println!("{:?}", record?.doi());
+/// }
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct SnapshotReader {
+    path: PathBuf,
+    options: ReadOptions,
+    on_error: ErrorPolicy,
+    error_log: Option<PathBuf>,
+    errors: Option<Arc<ErrorLog>>,
+}
+
+impl SnapshotReader {
+    /// Read from `path`, which may be a snapshot file or a directory to be searched recursively.
+    /// If `path` is `-` then read from STDIN instead.
+    pub fn new(path: impl Into<PathBuf>) -> SnapshotReader {
+        SnapshotReader {
+            path: path.into(),
+            options: ReadOptions::default(),
+            on_error: ErrorPolicy::default(),
+            error_log: None,
+            errors: None,
+        }
+    }
+
+    /// Send progress messages to STDERR.
+    pub fn verbose(mut self, verbose: bool) -> SnapshotReader {
+        self.options.verbose = verbose;
+        self
+    }
+
+    /// Number of files to read in parallel. If 0, the default, then one per CPU.
+    pub fn threads(mut self, threads: usize) -> SnapshotReader {
+        self.options.threads = threads;
+        self
+    }
+
+    /// Produce records in the order of input files, and the order of records within each file.
+    pub fn deterministic(mut self, deterministic: bool) -> SnapshotReader {
+        self.options.deterministic = deterministic;
+        self
+    }
+
+    /// What to do when a record or file can't be read.
+    pub fn on_error(mut self, on_error: ErrorPolicy) -> SnapshotReader {
+        self.on_error = on_error;
+        self
+    }
+
+    /// Only read records that match the filter.
+    pub fn filter(mut self, filter: RecordFilter) -> SnapshotReader {
+        self.options.filter = Arc::new(filter);
+        self
+    }
+
+    /// Log read errors as JSON Lines to this file, replacing it each time records are read.
+    pub fn error_log(mut self, error_log: impl Into<PathBuf>) -> SnapshotReader {
+        self.error_log = Some(error_log.into());
+        self
+    }
+
+    /// Report errors to a log shared with other readers, instead of starting a new one each time records are read.
+    /// The shared log's policy and file are used in place of `on_error` and `error_log`.
+    pub fn errors(mut self, errors: Arc<ErrorLog>) -> SnapshotReader {
+        self.errors = Some(errors);
+        self
+    }
+
+    /// The file or directory being read.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// List the snapshot files that will be read, and those that won't.
+    /// Empty when reading from STDIN.
+    pub fn files(&self) -> anyhow::Result<InputFiles> {
+        if is_stdio(&self.path) {
+            Ok(InputFiles::default())
+        } else {
+            find_input_files(&self.path)
+        }
+    }
+
+    /// The log that records will report errors to: the shared one if given, otherwise a new one.
+    pub(crate) fn error_log_for_read(&self) -> anyhow::Result<Arc<ErrorLog>> {
+        match self.errors {
+            Some(ref errors) => Ok(errors.clone()),
+            None => Ok(Arc::new(ErrorLog::new(
+                self.on_error,
+                self.error_log.as_deref(),
+                self.options.verbose,
+            )?)),
+        }
+    }
+
+    /// Start reading records on a background thread.
+    pub fn records(&self) -> anyhow::Result<Records> {
+        let InputFiles { files, skipped } = self.files()?;
+        let options = self.options.clone();
+        let errors = self.error_log_for_read()?;
+
+        let (tx, rx): (SyncSender<Record>, Receiver<Record>) = mpsc::sync_channel(CHANNEL_SIZE);
+        let read_errors = errors.clone();
+        let stdin = is_stdio(&self.path);
+        let read_thread = thread::spawn(move || {
+            if stdin {
+                read_stdin_to_channel(tx, &options, &read_errors)
+            } else {
+                read_paths_to_channel(&files, tx, &options, &read_errors)
+            }
+        });
+
+        Ok(Records {
+            rx,
+            read_thread: Some(read_thread),
+            errors,
+            skipped,
+        })
+    }
+}
+
+/// Iterator over records from a [`SnapshotReader`].
+/// If reading fails, the final item is the error.
+pub struct Records {
+    rx: Receiver<Record>,
+    read_thread: Option<JoinHandle<anyhow::Result<()>>>,
+    errors: Arc<ErrorLog>,
+    skipped: Vec<SkippedFile>,
+}
+
+impl Records {
+    /// Errors encountered so far.
+    /// Complete once the iterator is exhausted.
+    pub fn errors(&self) -> &ErrorLog {
+        &self.errors
+    }
+
+    /// Files found in the input that aren't being read, because they weren't recognised.
+    pub fn skipped(&self) -> &[SkippedFile] {
+        &self.skipped
+    }
+
+    /// Wait for the reader thread and return its result, flushing the error log.
+    fn finish(&mut self) -> Option<anyhow::Result<()>> {
+        let read_thread = self.read_thread.take()?;
+
+        let result = read_thread
+            .join()
+            .map_err(|err| anyhow::format_err!("Failed to join reader thread: {:?}", err))
+            .and_then(|result| result.map_err(|err| err.context("Failed to read input")))
+            .and_then(|_| self.errors.flush());
+
+        Some(result)
+    }
+}
+
+impl Iterator for Records {
+    type Item = anyhow::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.recv() {
+            Ok(record) => Some(Ok(record)),
+
+            // Channel closed, so the reader has finished.
+            Err(_) => match self.finish()? {
+                Ok(()) => None,
+                Err(err) => Some(Err(err)),
+            },
+        }
+    }
+}
+
+/// Return list of relevant files from path, recognised by their content. If it's a directory, recurse.
+/// Files that aren't recognised are listed with the reason.
+pub fn find_input_files(input_path: &Path) -> anyhow::Result<InputFiles> {
+    let mut found = InputFiles::default();
+
+    fn r(path: &Path, found: &mut InputFiles) -> anyhow::Result<()> {
+        if path.is_file() {
+            let path = path.to_path_buf();
+            match InputFormat::detect(&path) {
+                Ok(format) => found.files.push(InputFile { path, format }),
+                Err(reason) => found.skipped.push(SkippedFile { path, reason }),
+            }
+            Ok(())
+        } else if path.is_dir() {
+            // Sort so that files are always listed and read in the same order.
+            let mut entries = fs::read_dir(path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<PathBuf>, _>>()?;
+            entries.sort();
+
+            for path in entries {
+                r(&path, found)?
+            }
+
+            Ok(())
+        } else {
+            Ok(())
+        }
+    }
+
+    r(input_path, &mut found)?;
+
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// Write each file's lines to a new temporary directory.
+    fn input_dir(files: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            fs::write(dir.path().join(name), content).unwrap();
+        }
+        dir
+    }
+
+    /// Stream-parse a Crossref JSON document, returning the DOIs sent and the result.
+    fn read_json(json: &str) -> (Vec<String>, anyhow::Result<()>) {
+        let (tx, rx) = mpsc::sync_channel(100);
+        let source: Arc<Path> = Arc::from(Path::new("crossref.json"));
+        let result = read_json_to_channel(
+            json.as_bytes(),
+            &source,
+            &RecordSender::new(tx, &ReadOptions::default()),
+            false,
+        );
+
+        let dois = rx
+            .try_iter()
+            .filter_map(|record| record.raw_doi())
+            .collect();
+        (dois, result)
+    }
+
+    #[test]
+    fn crossref_items_among_other_keys() {
+        let (dois, result) = read_json(
+            r#"{"status": "ok", "message-type": {"nested": [1, {"items": []}]},
+                "items": [{"DOI": "10.1/a"}, {"DOI": "10.1/b"}],
+                "next-cursor": "x"}"#,
+        );
+        assert!(result.is_ok());
+        assert_eq!(dois, vec!["10.1/a", "10.1/b"]);
+
+        let (dois, result) = read_json(r#"{"items": [{"DOI": "10.1/c"}], "total": 1}"#);
+        assert!(result.is_ok());
+        assert_eq!(dois, vec!["10.1/c"]);
+    }
+
+    #[test]
+    fn crossref_items_missing() {
+        let (dois, result) = read_json(r#"{"message": {"items": [{"DOI": "10.1/a"}]}}"#);
+        assert!(dois.is_empty());
+        assert!(result.unwrap_err().to_string().contains("\"items\""));
+    }
+
+    #[test]
+    fn crossref_truncated() {
+        // Items are sent as they're parsed, before the error is found.
+        let (dois, result) = read_json(r#"{"items": [{"DOI": "10.1/a"}, {"DOI": "10."#);
+        assert_eq!(dois, vec!["10.1/a"]);
+        assert!(result.is_err());
+
+        let (_, result) = read_json(r#"{"items": []} trailing"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn crossref_disconnected_mid_array() {
+        let (tx, rx) = mpsc::sync_channel(1);
+        drop(rx);
+        let source: Arc<Path> = Arc::from(Path::new("crossref.json"));
+
+        // Stops at the first item, so the rest of the file isn't parsed.
+        let result = read_json_to_channel(
+            r#"{"items": [{"DOI": "10.1/a"}, not json"#.as_bytes(),
+            &source,
+            &RecordSender::new(tx, &ReadOptions::default()),
+            false,
+        );
+        assert!(result.unwrap_err().downcast_ref::<Disconnected>().is_some());
+    }
+
+    /// Read all records, failing the test if reading doesn't finish in time.
+    fn read_all(reader: SnapshotReader) -> Vec<anyhow::Result<Record>> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let records: Vec<anyhow::Result<Record>> = reader.records().unwrap().collect();
+            let _ = tx.send(records);
+        });
+        rx.recv_timeout(Duration::from_secs(30))
+            .expect("Reading didn't finish")
+    }
+
+    #[test]
+    fn deterministic_fail_with_one_thread_returns_error() {
+        let dir = input_dir(&[
+            ("a.jsonl", "{\"DOI\":\"10.1/a\"}\nnot json\n"),
+            ("b.jsonl", "{\"DOI\":\"10.1/b\"}\n"),
+            ("c.jsonl", "{\"DOI\":\"10.1/c\"}\n"),
+        ]);
+
+        let records = read_all(
+            SnapshotReader::new(dir.path())
+                .deterministic(true)
+                .threads(1)
+                .on_error(ErrorPolicy::Fail),
+        );
+
+        assert!(records.last().unwrap().is_err());
+    }
+
+    #[test]
+    fn deterministic_fail_stops_all_workers() {
+        let dir = input_dir(&[
+            ("a.jsonl", "{\"DOI\":\"10.1/a\"}\n"),
+            ("b.jsonl", "{\"DOI\":\"10.1/b\"}\nnot json\n"),
+            ("c.jsonl", "{\"DOI\":\"10.1/c\"}\n"),
+            ("d.jsonl", "{\"DOI\":\"10.1/d\"}\n"),
+            ("e.jsonl", "{\"DOI\":\"10.1/e\"}\n"),
+        ]);
+
+        for threads in 1..=4 {
+            let mut records = read_all(
+                SnapshotReader::new(dir.path())
+                    .deterministic(true)
+                    .threads(threads)
+                    .on_error(ErrorPolicy::Fail),
+            );
+
+            let err = records.pop().unwrap().unwrap_err();
+            assert!(format!("{:#}", err).contains("b.jsonl"), "{:#}", err);
+
+            // Everything before the bad record, and nothing from the files after it.
+            let dois: Vec<Option<String>> = records
+                .into_iter()
+                .map(|record| record.unwrap().doi())
+                .collect();
+            assert_eq!(
+                dois,
+                vec![Some(String::from("10.1/a")), Some(String::from("10.1/b"))]
+            );
+        }
+    }
+
+    #[test]
+    fn readers_share_error_log() {
+        let old = input_dir(&[("a.jsonl", "{\"DOI\":\"10.1/a\"}\nnot json\n")]);
+        let new = input_dir(&[("b.jsonl", "{\"DOI\":\"10.1/b\"}\nnot json\n")]);
+        let log_dir = tempfile::tempdir().unwrap();
+        let log = log_dir.path().join("errors.jsonl");
+
+        let errors = Arc::new(ErrorLog::new(ErrorPolicy::Skip, Some(&log), false).unwrap());
+
+        // Reading an input twice doesn't report its errors twice.
+        for dir in [&old, &new, &old] {
+            read_all(SnapshotReader::new(dir.path()).errors(errors.clone()));
+        }
+
+        assert_eq!(errors.records_skipped(), 2);
+        let logged = fs::read_to_string(&log).unwrap();
+        assert_eq!(logged.lines().count(), 2);
+        assert!(logged.contains("a.jsonl"));
+        assert!(logged.contains("b.jsonl"));
+    }
+
+    #[test]
+    fn error_log_replaced() {
+        let dir = input_dir(&[("a.jsonl", "{\"DOI\":\"10.1/a\"}\nnot json\n")]);
+        let log_dir = tempfile::tempdir().unwrap();
+        let log = log_dir.path().join("errors.jsonl");
+
+        for _ in 0..2 {
+            read_all(SnapshotReader::new(dir.path()).error_log(&log));
+        }
+
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 1);
+    }
+}
@@ -0,0 +1,52 @@
+use std::{path::Path, sync::Arc};
+
+use serde_json::Value;
+
+use crate::{
+    doi,
+    metadata::{get_agency, get_doi_from_record, Agency},
+    model::{common::CommonRecord, TypedRecord},
+};
+
+/// A metadata record read from a snapshot.
+#[derive(Clone, Debug)]
+pub struct Record {
+    /// The input file the record was read from.
+    pub source: Arc<Path>,
+
+    /// The record's metadata, as found in the snapshot.
+    pub value: Value,
+}
+
+impl Record {
+    pub fn new(source: Arc<Path>, value: Value) -> Record {
+        Record { source, value }
+    }
+
+    /// The record's DOI, if it has one, normalized with [`doi::normalize`].
+    pub fn doi(&self) -> Option<String> {
+        self.raw_doi().map(|raw_doi| doi::normalize(&raw_doi))
+    }
+
+    /// The record's DOI exactly as found in the snapshot, if it has one.
+    pub fn raw_doi(&self) -> Option<String> {
+        get_doi_from_record(&self.value)
+    }
+
+    /// The agency the record came from, if recognised.
+    pub fn agency(&self) -> Option<Agency> {
+        get_agency(&self.value)
+    }
+
+    /// Parse the record according to its agency's schema.
+    /// None if the agency isn't recognised.
+    pub fn typed(&self) -> Option<TypedRecord> {
+        TypedRecord::from_value(&self.value)
+    }
+
+    /// Normalize the record to the common shape shared by all agencies.
+    /// None if the agency isn't recognised.
+    pub fn to_common(&self) -> Option<CommonRecord> {
+        self.typed().map(|typed| typed.to_common())
+    }
+}
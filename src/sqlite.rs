@@ -0,0 +1,513 @@
+//! Export to a SQLite database with a relational schema, for querying snapshots with plain SQL.
+//!
+//! Records from both agencies are normalized to the same tables, keyed by normalized DOI.
+//! The schema is in [`SCHEMA`]. Titles and abstracts are indexed for full-text search with FTS5.
+
+use std::{fs, path::Path, sync::mpsc::Receiver};
+
+use rusqlite::{params, Connection, Transaction};
+use serde_json::Value;
+
+use crate::{
+    doi::normalize,
+    model::{common::CommonRecord, flat::FlatRecord, TypedRecord},
+};
+
+/// Tables and indexes. The full-text index is built once all records are written.
+pub const SCHEMA: &str = "
+CREATE TABLE works (
+    doi TEXT PRIMARY KEY,
+    agency TEXT,
+    resource_type TEXT,
+    title TEXT,
+    abstract TEXT,
+    publisher TEXT,
+    publication_year INTEGER,
+    license TEXT,
+    updated TEXT,
+    json TEXT NOT NULL
+);
+
+CREATE TABLE contributors (
+    doi TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    name TEXT,
+    given_name TEXT,
+    family_name TEXT,
+    orcid TEXT,
+    ror TEXT,
+    affiliation TEXT
+);
+
+CREATE TABLE identifiers (
+    doi TEXT NOT NULL,
+    identifier_type TEXT,
+    identifier TEXT NOT NULL
+);
+
+CREATE TABLE citations (
+    doi TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    cited_doi TEXT,
+    unstructured TEXT,
+    article_title TEXT,
+    journal_title TEXT,
+    author TEXT,
+    year TEXT
+);
+
+CREATE TABLE related_identifiers (
+    doi TEXT NOT NULL,
+    identifier TEXT NOT NULL,
+    identifier_type TEXT,
+    relation_type TEXT
+);
+
+CREATE INDEX contributors_doi ON contributors (doi);
+CREATE INDEX contributors_orcid ON contributors (orcid);
+CREATE INDEX identifiers_doi ON identifiers (doi);
+CREATE INDEX identifiers_identifier ON identifiers (identifier);
+CREATE INDEX citations_doi ON citations (doi);
+CREATE INDEX citations_cited_doi ON citations (cited_doi);
+CREATE INDEX related_identifiers_doi ON related_identifiers (doi);
+CREATE INDEX related_identifiers_identifier ON related_identifiers (identifier);
+
+CREATE VIRTUAL TABLE works_fts USING fts5 (title, abstract, content = 'works', content_rowid = 'rowid');
+";
+
+/// Tables with rows for each work, which are cleared when a DOI is written again.
+const CHILD_TABLES: &[&str] = &[
+    "contributors",
+    "identifiers",
+    "citations",
+    "related_identifiers",
+];
+
+/// Number of records written in each transaction.
+const TRANSACTION_SIZE: usize = 100_000;
+
+/// Write records to a new SQLite database, replacing any existing one.
+/// Where more than one record has the same DOI, the last is kept.
+/// Records without DOIs are skipped, and the number skipped is returned.
+pub fn write_chan_to_sqlite(
+    output_file: &Path,
+    rx: Receiver<Value>,
+    verbose: bool,
+) -> anyhow::Result<usize> {
+    if output_file.exists() {
+        fs::remove_file(output_file)?;
+    }
+
+    let mut db = Connection::open(output_file)?;
+
+    // The file is new, so if writing fails part way it's discarded anyway.
+    db.pragma_update(None, "journal_mode", "OFF")?;
+    db.pragma_update(None, "synchronous", "OFF")?;
+    db.execute_batch(SCHEMA)?;
+
+    let mut count: usize = 0;
+    let mut without_doi: usize = 0;
+    let mut rx = rx.iter().peekable();
+    while rx.peek().is_some() {
+        let txn = db.transaction()?;
+        for entry in rx.by_ref().take(TRANSACTION_SIZE) {
+            count += 1;
+            if verbose && count.is_multiple_of(10000) {
+                eprintln!("Written {} entries to {:?}", count, output_file);
+            }
+
+            if !insert(&txn, &entry)? {
+                without_doi += 1;
+            }
+        }
+        txn.commit()?;
+    }
+
+    if verbose {
+        eprintln!("Building full-text index in {:?}", output_file);
+    }
+    db.execute("INSERT INTO works_fts (works_fts) VALUES ('rebuild')", [])?;
+
+    Ok(without_doi)
+}
+
+/// Insert a record into all tables, replacing any earlier record with the same DOI.
+/// Returns false if the record has no DOI, so wasn't inserted.
+fn insert(txn: &Transaction, value: &Value) -> anyhow::Result<bool> {
+    let Some(typed) = TypedRecord::from_value(value) else {
+        // Records from an unrecognised agency only go in the works table.
+        let flat = FlatRecord::from_value(value);
+        let Some(doi) = flat.doi.as_deref().map(normalize) else {
+            return Ok(false);
+        };
+        replace_work(txn, &doi, &flat, None)?;
+        return Ok(true);
+    };
+
+    let common = typed.to_common();
+    let Some(doi) = common.doi.clone() else {
+        return Ok(false);
+    };
+
+    let flat = FlatRecord::from_typed(&typed, &common, value.to_string());
+    replace_work(txn, &doi, &flat, abstract_text(&typed).as_deref())?;
+
+    insert_contributors(txn, &doi, &common)?;
+
+    let mut identifiers = txn.prepare_cached(
+        "INSERT INTO identifiers (doi, identifier_type, identifier) VALUES (?1, ?2, ?3)",
+    )?;
+    for (identifier_type, identifier) in identifiers_of(&typed) {
+        identifiers.execute(params![doi, identifier_type, identifier])?;
+    }
+
+    let mut citations = txn.prepare_cached(
+        "INSERT INTO citations (doi, position, cited_doi, unstructured, article_title, journal_title, author, year)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    match &typed {
+        TypedRecord::Crossref(work) => {
+            for (position, reference) in work.reference.iter().enumerate() {
+                citations.execute(params![
+                    doi,
+                    position,
+                    reference.doi.as_deref().map(normalize),
+                    reference.unstructured,
+                    reference.article_title,
+                    reference.journal_title,
+                    reference.author,
+                    reference.year,
+                ])?;
+            }
+        }
+
+        // DataCite only has references as related identifiers.
+        TypedRecord::DataCite(_) => {
+            let references = common.related_identifiers.iter().filter(|related| {
+                related.relation_type.as_deref() == Some("References")
+                    && related.identifier_type.as_deref() == Some("DOI")
+            });
+            for (position, related) in references.enumerate() {
+                citations.execute(params![
+                    doi,
+                    position,
+                    related.identifier,
+                    None::<String>,
+                    None::<String>,
+                    None::<String>,
+                    None::<String>,
+                    None::<String>,
+                ])?;
+            }
+        }
+    }
+
+    let mut related_identifiers = txn.prepare_cached(
+        "INSERT INTO related_identifiers (doi, identifier, identifier_type, relation_type) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for related in common.related_identifiers.iter() {
+        related_identifiers.execute(params![
+            doi,
+            related.identifier,
+            related.identifier_type,
+            related.relation_type,
+        ])?;
+    }
+
+    Ok(true)
+}
+
+/// Insert or replace the row in the works table, and delete rows for any earlier record from the other tables.
+fn replace_work(
+    txn: &Transaction,
+    doi: &str,
+    flat: &FlatRecord,
+    abstract_text: Option<&str>,
+) -> anyhow::Result<()> {
+    let replaced = txn
+        .prepare_cached("DELETE FROM works WHERE doi = ?1")?
+        .execute([doi])?;
+    if replaced > 0 {
+        for table in CHILD_TABLES {
+            txn.prepare_cached(&format!("DELETE FROM {} WHERE doi = ?1", table))?
+                .execute([doi])?;
+        }
+    }
+
+    txn.prepare_cached(
+        "INSERT INTO works (doi, agency, resource_type, title, abstract, publisher, publication_year, license, updated, json)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?
+    .execute(params![
+        doi,
+        flat.agency,
+        flat.resource_type,
+        flat.title,
+        abstract_text,
+        flat.publisher,
+        flat.publication_year,
+        flat.license,
+        flat.updated,
+        flat.json,
+    ])?;
+
+    Ok(())
+}
+
+fn insert_contributors(txn: &Transaction, doi: &str, common: &CommonRecord) -> anyhow::Result<()> {
+    let mut contributors = txn.prepare_cached(
+        "INSERT INTO contributors (doi, position, name, given_name, family_name, orcid, ror, affiliation)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+    )?;
+    for (position, creator) in common.creators.iter().enumerate() {
+        contributors.execute(params![
+            doi,
+            position,
+            creator.name,
+            creator.given_name,
+            creator.family_name,
+            creator.orcid,
+            creator.ror,
+            creator
+                .affiliations
+                .first()
+                .and_then(|affiliation| affiliation.name.clone()),
+        ])?;
+    }
+    Ok(())
+}
+
+/// The abstract, with any markup removed. For DataCite, the first description of type `Abstract`.
+fn abstract_text(typed: &TypedRecord) -> Option<String> {
+    let text = match typed {
+        TypedRecord::Crossref(work) => work.abstract_text.as_deref(),
+        TypedRecord::DataCite(doi) => doi
+            .descriptions
+            .iter()
+            .find(|description| description.description_type.as_deref() == Some("Abstract"))
+            .and_then(|description| description.description.as_deref()),
+    }?;
+
+    Some(strip_tags(text))
+}
+
+/// Remove XML tags, such as JATS markup in Crossref abstracts, and collapse whitespace.
+fn strip_tags(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                stripped.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+    stripped.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Identifiers of the work itself, as (type, identifier): ISSNs, ISBNs and URL for Crossref,
+/// alternate identifiers and URL for DataCite.
+fn identifiers_of(typed: &TypedRecord) -> Vec<(String, String)> {
+    let mut identifiers = vec![];
+    match typed {
+        TypedRecord::Crossref(work) => {
+            for issn in work.issn.iter() {
+                identifiers.push((String::from("ISSN"), issn.clone()));
+            }
+            for isbn in work.isbn.iter() {
+                identifiers.push((String::from("ISBN"), isbn.clone()));
+            }
+            if let Some(url) = &work.url {
+                identifiers.push((String::from("URL"), url.clone()));
+            }
+        }
+        TypedRecord::DataCite(doi) => {
+            for identifier in doi.identifiers.iter() {
+                if let Some(value) = &identifier.identifier {
+                    identifiers.push((
+                        identifier
+                            .identifier_type
+                            .clone()
+                            .unwrap_or_else(|| String::from("Other")),
+                        value.clone(),
+                    ));
+                }
+            }
+            if let Some(url) = &doi.url {
+                identifiers.push((String::from("URL"), url.clone()));
+            }
+        }
+    }
+    identifiers
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn crossref() -> Value {
+        json!({
+            "DOI": "10.5555/CR1",
+            "type": "journal-article",
+            "title": ["Pardalote nesting"],
+            "abstract": "<jats:p>Nests in <jats:italic>banks</jats:italic>.</jats:p>",
+            "author": [
+                {"given": "Ada", "family": "Lovelace", "ORCID": "http://orcid.org/0000-0002-1825-0097", "affiliation": [{"name": "Uni"}]},
+                {"name": "Consortium"}
+            ],
+            "reference": [{"key": "r1", "DOI": "10.5555/CITED"}, {"key": "r2", "unstructured": "A book"}],
+            "relation": {"is-preprint-of": [{"id-type": "doi", "id": "10.5555/PUB"}]},
+            "ISSN": ["1234-5678"]
+        })
+    }
+
+    fn datacite() -> Value {
+        json!({"attributes": {
+            "doi": "10.6666/DC1",
+            "titles": [{"title": "Bird counts"}],
+            "creators": [{"name": "Smith, J", "nameIdentifiers": [{"nameIdentifier": "0000-0002-1825-0097", "nameIdentifierScheme": "ORCID"}]}],
+            "descriptions": [{"description": "Counts of pardalotes", "descriptionType": "Abstract"}],
+            "relatedIdentifiers": [
+                {"relatedIdentifier": "10.5555/CR1", "relatedIdentifierType": "DOI", "relationType": "References"},
+                {"relatedIdentifier": "https://example.org", "relatedIdentifierType": "URL", "relationType": "IsSupplementTo"}
+            ],
+            "types": {"resourceTypeGeneral": "Dataset"}
+        }})
+    }
+
+    /// Write records to a new database, returning it open and the number skipped without DOIs.
+    fn write(records: Vec<Value>) -> (tempfile::TempDir, Connection, usize) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.sqlite");
+        let (tx, rx) = channel();
+        for record in records {
+            tx.send(record).unwrap();
+        }
+        drop(tx);
+
+        let without_doi = write_chan_to_sqlite(&path, rx, false).unwrap();
+        let db = Connection::open(&path).unwrap();
+        (dir, db, without_doi)
+    }
+
+    fn strings(db: &Connection, sql: &str) -> Vec<String> {
+        let mut statement = db.prepare(sql).unwrap();
+        let rows = statement
+            .query_map([], |row| row.get::<_, String>(0))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn write_tables() {
+        let (_dir, db, without_doi) =
+            write(vec![crossref(), json!({"title": "No DOI"}), datacite()]);
+        assert_eq!(without_doi, 1);
+
+        assert_eq!(
+            strings(
+                &db,
+                "SELECT doi || ' ' || resource_type || ' ' || abstract FROM works ORDER BY doi"
+            ),
+            vec![
+                "10.5555/cr1 journal-article Nests in banks .",
+                "10.6666/dc1 Dataset Counts of pardalotes",
+            ]
+        );
+
+        assert_eq!(
+            strings(&db, "SELECT doi || ' ' || position || ' ' || name || ' ' || ifnull(orcid, '-') FROM contributors ORDER BY doi, position"),
+            vec![
+                "10.5555/cr1 0 Lovelace, Ada https://orcid.org/0000-0002-1825-0097",
+                "10.5555/cr1 1 Consortium -",
+                "10.6666/dc1 0 Smith, J https://orcid.org/0000-0002-1825-0097",
+            ]
+        );
+
+        assert_eq!(
+            strings(&db, "SELECT doi || ' ' || ifnull(cited_doi, unstructured) FROM citations ORDER BY doi, position"),
+            vec!["10.5555/cr1 10.5555/cited", "10.5555/cr1 A book", "10.6666/dc1 10.5555/cr1"]
+        );
+
+        assert_eq!(
+            strings(&db, "SELECT doi || ' ' || relation_type || ' ' || identifier FROM related_identifiers ORDER BY doi, identifier"),
+            vec![
+                "10.5555/cr1 References 10.5555/cited",
+                "10.5555/cr1 IsPreprintOf 10.5555/pub",
+                "10.6666/dc1 References 10.5555/cr1",
+                "10.6666/dc1 IsSupplementTo https://example.org",
+            ]
+        );
+
+        assert_eq!(
+            strings(
+                &db,
+                "SELECT identifier_type || ' ' || identifier FROM identifiers"
+            ),
+            vec!["ISSN 1234-5678"]
+        );
+    }
+
+    #[test]
+    fn same_doi_replaced() {
+        let newer =
+            json!({"DOI": "10.5555/cr1", "title": ["Revised"], "author": [{"name": "Solo"}]});
+        let (_dir, db, _) = write(vec![crossref(), datacite(), newer]);
+
+        assert_eq!(
+            strings(&db, "SELECT title FROM works ORDER BY doi"),
+            vec!["Revised", "Bird counts"]
+        );
+        assert_eq!(
+            strings(
+                &db,
+                "SELECT name FROM contributors WHERE doi = '10.5555/cr1'"
+            ),
+            vec!["Solo"]
+        );
+        for table in ["citations", "related_identifiers", "identifiers"] {
+            let rows: i64 = db
+                .query_row(
+                    &format!("SELECT count(*) FROM {} WHERE doi = '10.5555/cr1'", table),
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(rows, 0, "{}", table);
+        }
+
+        // The other record's rows are untouched.
+        assert_eq!(
+            strings(&db, "SELECT doi FROM citations"),
+            vec!["10.6666/dc1"]
+        );
+    }
+
+    #[test]
+    fn full_text_search() {
+        let (_dir, db, _) = write(vec![crossref(), datacite()]);
+        let search = |query: &str| {
+            strings(
+                &db,
+                &format!(
+                    "SELECT works.doi FROM works_fts JOIN works ON works.rowid = works_fts.rowid
+                     WHERE works_fts MATCH '{}' ORDER BY works.doi",
+                    query
+                ),
+            )
+        };
+
+        assert_eq!(search("banks"), vec!["10.5555/cr1"]);
+        assert_eq!(search("pardalotes"), vec!["10.6666/dc1"]);
+        assert_eq!(search("title:pardalote"), vec!["10.5555/cr1"]);
+        assert!(search("jats").is_empty());
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use crate::{doi::normalize, errors::ErrorLog, metadata::get_doi_from_record};
+
+/// Accumulates stats over a stream of records.
+#[derive(Debug, Default)]
+pub struct Stats {
+    count: usize,
+    total_json_chars: usize,
+    total_doi_bytes: usize,
+    total_doi_chars: usize,
+    doi_chars_frequencies: BTreeMap<usize, usize>,
+    doi_bytes_frequencies: BTreeMap<usize, usize>,
+    json_chars_frequencies: BTreeMap<usize, usize>,
+    max_doi_codepoint: char,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    pub fn add(&mut self, record: &Value) {
+        self.count += 1;
+
+        let json_chars = record.to_string().len();
+        self.total_json_chars += json_chars;
+
+        // Integer division to bucket into 1kb buckets.
+        let json_chars_bucketed = (json_chars / 1024) * 1024;
+        *self
+            .json_chars_frequencies
+            .entry(json_chars_bucketed)
+            .or_insert(0) += 1;
+
+        // Normalized, as DOIs are compared everywhere else.
+        if let Some(doi) = get_doi_from_record(record).map(|doi| normalize(&doi)) {
+            let doi_chars = doi.chars().count();
+
+            // String::len() measures bytes not chars.
+            let doi_bytes = doi.len();
+
+            if let Some(this_max_doi_codepoint) = doi.chars().max() {
+                self.max_doi_codepoint = this_max_doi_codepoint.max(self.max_doi_codepoint);
+            }
+
+            self.total_doi_chars += doi_chars;
+            *self.doi_chars_frequencies.entry(doi_chars).or_insert(0) += 1;
+
+            self.total_doi_bytes += doi_bytes;
+            *self.doi_bytes_frequencies.entry(doi_bytes).or_insert(0) += 1;
+        }
+    }
+
+    /// Print the stats, including read errors, to STDOUT.
+    pub fn print(&self, errors: &ErrorLog) {
+        let count = self.count;
+        let total_json_chars = self.total_json_chars;
+        let total_doi_chars = self.total_doi_chars;
+        let total_doi_bytes = self.total_doi_bytes;
+        let max_doi_codepoint = self.max_doi_codepoint;
+
+        let mean_json_chars = (total_json_chars as f32) / (count as f32);
+        let mean_doi_chars = (total_doi_chars as f32) / (count as f32);
+        let mean_doi_bytes = (total_doi_bytes as f32) / (count as f32);
+
+        let mode_doi_chars = mode(&self.doi_chars_frequencies);
+        let mode_doi_bytes = mode(&self.doi_bytes_frequencies);
+        let mode_json_chars = mode(&self.json_chars_frequencies);
+
+        println!("Record count: {count}");
+        println!();
+        println!("Errors:");
+        println!("Unparsable records skipped: {}", errors.records_skipped());
+        println!("Files not fully read: {}", errors.files_abandoned());
+        println!();
+        println!("JSON:");
+        println!("Total JSON chars: {total_json_chars}");
+        println!("Mean JSON chars: {mean_json_chars}");
+        println!("Modal JSON chars: {mode_json_chars}");
+
+        println!();
+        println!("DOIs:");
+        println!("Total DOI chars: {total_doi_chars}");
+        println!("Mean DOI chars: {mean_doi_chars}");
+        println!("Modal DOI chars: {mode_doi_chars}");
+
+        println!();
+
+        println!("Total DOI bytes: {total_doi_bytes}");
+        println!("Mean DOI bytes: {mean_doi_bytes}");
+        println!("Modal DOI bytes: {mode_doi_bytes}");
+
+        println!(
+            "Max Unicode code point: {} : {}",
+            max_doi_codepoint, max_doi_codepoint as u32
+        );
+
+        println!();
+        println!("Frequencies:");
+        println!("JSON chars frequencies (bins of 1KiB):");
+
+        for (length, frequency) in self.json_chars_frequencies.iter() {
+            println!("{length},{frequency}");
+        }
+        println!();
+        println!();
+
+        println!("DOI chars frequencies:");
+
+        for (length, frequency) in self.doi_chars_frequencies.iter() {
+            println!("{length},{frequency}");
+        }
+    }
+}
+
+/// Most frequent value in a frequency table, or 0 if empty.
+fn mode(frequencies: &BTreeMap<usize, usize>) -> usize {
+    frequencies
+        .iter()
+        .max_by_key(|&(_, count)| count)
+        .map(|(value, _)| *value)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn dois_are_normalized() {
+        let mut stats = Stats::new();
+        stats.add(&json!({"DOI": "https://doi.org/10.1/ABC"}));
+        stats.add(&json!({"DOI": "10.1/abc"}));
+
+        assert_eq!(stats.total_doi_chars, 16);
+        assert_eq!(stats.doi_chars_frequencies.get(&8), Some(&2));
+        assert_eq!(stats.max_doi_codepoint, 'c');
+    }
+}
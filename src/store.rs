@@ -0,0 +1,202 @@
+//! Export to a redb embedded key-value store, for point lookups by DOI without a database server.
+//!
+//! The store has a table of records keyed by normalized DOI, and tables of DOIs by prefix and by agency.
+//! Services can open it read-only with [`SnapshotStore`], or directly with redb using the table definitions here.
+
+use std::{fs, path::Path, sync::mpsc::Receiver};
+
+use redb::{
+    Database, MultimapTableDefinition, ReadOnlyDatabase, ReadableDatabase, TableDefinition,
+};
+use serde_json::Value;
+
+use crate::{
+    doi::{self, normalize},
+    metadata::{get_agency, get_doi_from_record, Agency},
+};
+
+/// Normalized DOI → record JSON.
+pub const RECORDS: TableDefinition<&str, &str> = TableDefinition::new("records");
+
+/// DOI prefix, e.g. `10.5555` → normalized DOIs.
+pub const PREFIXES: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("prefixes");
+
+/// Agency, `crossref` or `datacite` → normalized DOIs.
+pub const AGENCIES: MultimapTableDefinition<&str, &str> = MultimapTableDefinition::new("agencies");
+
+/// Number of records written in each transaction.
+const TRANSACTION_SIZE: usize = 100_000;
+
+/// Write records to a new redb file, replacing any existing one.
+/// Where more than one record has the same DOI, the last is kept.
+/// Records without DOIs are skipped, and the number skipped is returned.
+pub fn write_chan_to_redb(
+    output_file: &Path,
+    rx: Receiver<Value>,
+    verbose: bool,
+) -> anyhow::Result<usize> {
+    if output_file.exists() {
+        fs::remove_file(output_file)?;
+    }
+
+    let db = Database::create(output_file)?;
+
+    let mut count: usize = 0;
+    let mut without_doi: usize = 0;
+    let mut rx = rx.iter().peekable();
+    while rx.peek().is_some() {
+        let txn = db.begin_write()?;
+        {
+            let mut records = txn.open_table(RECORDS)?;
+            let mut prefixes = txn.open_multimap_table(PREFIXES)?;
+            let mut agencies = txn.open_multimap_table(AGENCIES)?;
+
+            for entry in rx.by_ref().take(TRANSACTION_SIZE) {
+                count += 1;
+                if verbose && count.is_multiple_of(10000) {
+                    eprintln!("Written {} entries to {:?}", count, output_file);
+                }
+
+                let Some(doi) = get_doi_from_record(&entry).map(|doi| normalize(&doi)) else {
+                    without_doi += 1;
+                    continue;
+                };
+
+                records.insert(doi.as_str(), entry.to_string().as_str())?;
+
+                if let Some(prefix) = doi::prefix(&doi) {
+                    prefixes.insert(prefix, doi.as_str())?;
+                }
+
+                if let Some(agency) = get_agency(&entry) {
+                    agencies.insert(agency.to_string().as_str(), doi.as_str())?;
+                }
+            }
+        }
+        txn.commit()?;
+    }
+
+    // Create the tables even if there were no records, so readers can open them.
+    if count == 0 {
+        let txn = db.begin_write()?;
+        txn.open_table(RECORDS)?;
+        txn.open_multimap_table(PREFIXES)?;
+        txn.open_multimap_table(AGENCIES)?;
+        txn.commit()?;
+    }
+
+    Ok(without_doi)
+}
+
+/// A redb snapshot opened read-only, so that any number of processes can read it at once.
+pub struct SnapshotStore {
+    db: ReadOnlyDatabase,
+}
+
+impl SnapshotStore {
+    pub fn open(path: &Path) -> anyhow::Result<SnapshotStore> {
+        Ok(SnapshotStore {
+            db: ReadOnlyDatabase::open(path)?,
+        })
+    }
+
+    /// The record for a DOI, normalized before lookup.
+    pub fn get(&self, doi: &str) -> anyhow::Result<Option<Value>> {
+        let txn = self.db.begin_read()?;
+        let records = txn.open_table(RECORDS)?;
+
+        match records.get(normalize(doi).as_str())? {
+            Some(json) => Ok(Some(serde_json::from_str(json.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Normalized DOIs with a prefix, such as `10.5555`, in sorted order.
+    pub fn dois_with_prefix(&self, prefix: &str) -> anyhow::Result<Vec<String>> {
+        let prefix = normalize(prefix.trim_end_matches('/'));
+        self.multimap_values(PREFIXES, &prefix)
+    }
+
+    /// Normalized DOIs from an agency, in sorted order.
+    pub fn dois_from_agency(&self, agency: Agency) -> anyhow::Result<Vec<String>> {
+        self.multimap_values(AGENCIES, &agency.to_string())
+    }
+
+    fn multimap_values(
+        &self,
+        table: MultimapTableDefinition<&str, &str>,
+        key: &str,
+    ) -> anyhow::Result<Vec<String>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_multimap_table(table)?;
+
+        let mut values = vec![];
+        for value in table.get(key)? {
+            values.push(value?.value().to_string());
+        }
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.redb");
+
+        let (tx, rx) = channel();
+        for record in [
+            json!({"DOI": "10.5555/A", "n": 1}),
+            json!({"title": "No DOI"}),
+            json!({"attributes": {"doi": "10.6666/b"}, "n": 2}),
+            json!({"DOI": "10.5555/c", "n": 3}),
+            json!({"DOI": "https://doi.org/10.5555/a", "n": 4}),
+        ] {
+            tx.send(record).unwrap();
+        }
+        drop(tx);
+        assert_eq!(write_chan_to_redb(&path, rx, false).unwrap(), 1);
+
+        let store = SnapshotStore::open(&path).unwrap();
+
+        // The last record for a DOI wins.
+        assert_eq!(store.get("doi:10.5555/a").unwrap().unwrap()["n"], json!(4));
+        assert_eq!(store.get("10.6666/B").unwrap().unwrap()["n"], json!(2));
+        assert!(store.get("10.5555/missing").unwrap().is_none());
+
+        assert_eq!(
+            store.dois_with_prefix("10.5555/").unwrap(),
+            vec!["10.5555/a", "10.5555/c"]
+        );
+        assert!(store.dois_with_prefix("10.7777").unwrap().is_empty());
+        assert_eq!(
+            store.dois_from_agency(Agency::Crossref).unwrap(),
+            vec!["10.5555/a", "10.5555/c"]
+        );
+        assert_eq!(
+            store.dois_from_agency(Agency::DataCite).unwrap(),
+            vec!["10.6666/b"]
+        );
+    }
+
+    #[test]
+    fn empty_store_can_be_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.redb");
+
+        let (tx, rx) = channel::<Value>();
+        drop(tx);
+        assert_eq!(write_chan_to_redb(&path, rx, false).unwrap(), 0);
+
+        let store = SnapshotStore::open(&path).unwrap();
+        assert!(store.get("10.5555/a").unwrap().is_none());
+        assert!(store.dois_from_agency(Agency::Crossref).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+//! Applying incremental update files on top of a base snapshot.
+
+use crate::group::{newest, Entry};
+
+/// Tag for records from the base snapshot. Updates are tagged from 1, in the order they're applied.
+pub const BASE: u32 = 0;
+
+/// Counts of what happened to each DOI.
+#[derive(Debug, Default)]
+pub struct UpdateSummary {
+    /// DOIs in an update but not the base.
+    pub inserted: usize,
+
+    /// DOIs in the base replaced by a newer record from an update.
+    pub replaced: usize,
+
+    /// DOIs in the base not replaced, either because they weren't in an update or the update wasn't newer.
+    pub kept: usize,
+
+    /// Records in updates that weren't applied, because the base or another update had a newer record for the DOI.
+    pub superseded: usize,
+
+    /// Records without DOIs, which are all kept.
+    pub without_doi: usize,
+}
+
+impl UpdateSummary {
+    pub fn new() -> UpdateSummary {
+        UpdateSummary::default()
+    }
+
+    /// Print the summary to STDERR.
+    pub fn print(&self) {
+        eprintln!("Update:");
+        eprintln!("DOIs inserted: {}", self.inserted);
+        eprintln!("DOIs replaced: {}", self.replaced);
+        eprintln!("DOIs kept: {}", self.kept);
+        eprintln!("Update records superseded: {}", self.superseded);
+        eprintln!("Records without DOIs: {}", self.without_doi);
+    }
+}
+
+/// The entries to write for one DOI, from a group of records tagged [`BASE`] or by update.
+///
+/// The newest record in the updates replaces the base record if its updated timestamp is later.
+/// If the DOI isn't in the base, the newest record in the updates is inserted.
+/// Where the base or the updates have more than one record for the DOI, the newest is used,
+/// and later updates win ties. Records without DOIs are all kept.
+pub fn apply<'a>(group: &'a [Entry], summary: &mut UpdateSummary) -> Vec<&'a Entry> {
+    if group.first().is_some_and(|entry| entry.doi.is_none()) {
+        summary.without_doi += group.len();
+        return group.iter().collect();
+    }
+
+    let base = newest(group.iter().filter(|entry| entry.tag == BASE));
+    let update = newest(group.iter().filter(|entry| entry.tag != BASE));
+    let updates = group.iter().filter(|entry| entry.tag != BASE).count();
+
+    let chosen = match (base, update) {
+        (Some(base), Some(update)) if update.updated > base.updated => {
+            summary.replaced += 1;
+            update
+        }
+        (Some(base), _) => {
+            summary.kept += 1;
+            base
+        }
+        (None, Some(update)) => {
+            summary.inserted += 1;
+            update
+        }
+        (None, None) => return vec![],
+    };
+
+    summary.superseded += updates - usize::from(chosen.tag != BASE);
+
+    vec![chosen]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::{group::Grouper, record::Record};
+
+    /// Apply updates to a base, returning the "n" of each record written, sorted, and the summary.
+    fn update(records: Vec<(u32, Value)>) -> (Vec<i64>, UpdateSummary) {
+        let dir = tempfile::tempdir().unwrap();
+        let source: Arc<Path> = Arc::from(Path::new("input.jsonl"));
+        let mut grouper = Grouper::new(dir.path(), 2).unwrap();
+        for (tag, value) in records {
+            grouper
+                .add(&Record::new(source.clone(), value), tag)
+                .unwrap();
+        }
+
+        let mut summary = UpdateSummary::new();
+        let mut groups = grouper.finish().unwrap();
+        let mut written = vec![];
+        while let Some(group) = groups.next() {
+            let group = group.unwrap();
+            for entry in apply(&group, &mut summary) {
+                written.push(groups.read(entry).unwrap().value["n"].as_i64().unwrap());
+            }
+        }
+        written.sort_unstable();
+        (written, summary)
+    }
+
+    fn crossref(doi: &str, indexed: &str, n: i64) -> Value {
+        json!({"DOI": doi, "indexed": {"date-time": indexed}, "n": n})
+    }
+
+    #[test]
+    fn newer_update_replaces_base() {
+        let (written, summary) = update(vec![
+            (BASE, crossref("10.1/a", "2024-01-01T00:00:00Z", 1)),
+            (1, crossref("10.1/A", "2024-01-01T00:00:00.5Z", 2)),
+        ]);
+        assert_eq!(written, vec![2]);
+        assert_eq!(summary.replaced, 1);
+        assert_eq!(summary.superseded, 0);
+    }
+
+    #[test]
+    fn older_or_equal_update_keeps_base() {
+        let (written, summary) = update(vec![
+            (BASE, crossref("10.1/a", "2024-01-02T00:00:00Z", 1)),
+            (1, crossref("10.1/a", "2024-01-01T00:00:00Z", 2)),
+            (BASE, crossref("10.1/b", "2024-01-01T00:00:00Z", 3)),
+            (1, crossref("10.1/b", "2024-01-01T00:00:00Z", 4)),
+        ]);
+        assert_eq!(written, vec![1, 3]);
+        assert_eq!(summary.kept, 2);
+        assert_eq!(summary.superseded, 2);
+    }
+
+    #[test]
+    fn newest_update_inserted_and_later_update_wins_ties() {
+        let (written, summary) = update(vec![
+            (1, crossref("10.1/a", "2024-01-01T00:00:00Z", 1)),
+            (2, crossref("10.1/a", "2024-01-03T00:00:00Z", 2)),
+            (3, crossref("10.1/a", "2024-01-02T00:00:00Z", 3)),
+            (1, crossref("10.1/b", "2024-01-01T00:00:00Z", 4)),
+            (2, crossref("10.1/b", "2024-01-01T00:00:00Z", 5)),
+        ]);
+        assert_eq!(written, vec![2, 5]);
+        assert_eq!(summary.inserted, 2);
+        assert_eq!(summary.superseded, 3);
+    }
+
+    #[test]
+    fn base_kept_and_records_without_dois_written() {
+        let (written, summary) = update(vec![
+            (BASE, crossref("10.1/a", "2024-01-01T00:00:00Z", 1)),
+            (BASE, json!({"n": 2})),
+            (1, json!({"n": 3})),
+        ]);
+        assert_eq!(written, vec![1, 2, 3]);
+        assert_eq!(summary.kept, 1);
+        assert_eq!(summary.without_doi, 2);
+    }
+}
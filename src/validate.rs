@@ -0,0 +1,98 @@
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use crate::{
+    doi::{self, DoiProblem},
+    record::Record,
+};
+
+/// Used in place of a prefix for DOIs without one.
+const NO_PREFIX: &str = "(none)";
+
+/// DOIs with problems, by prefix.
+type ProblemsByPrefix = BTreeMap<String, Vec<(String, Vec<DoiProblem>)>>;
+
+/// Accumulates records with missing or problematic DOIs, grouped by source file and prefix.
+#[derive(Debug, Default)]
+pub struct DoiValidator {
+    checked: usize,
+
+    /// Number of records with each problem. A record may have more than one.
+    counts: BTreeMap<DoiProblem, usize>,
+
+    /// Source file → prefix → DOIs with problems.
+    problems: BTreeMap<Arc<Path>, ProblemsByPrefix>,
+
+    /// Source file → number of records without DOIs.
+    missing: BTreeMap<Arc<Path>, usize>,
+}
+
+impl DoiValidator {
+    pub fn new() -> DoiValidator {
+        DoiValidator::default()
+    }
+
+    pub fn add(&mut self, record: &Record) {
+        self.checked += 1;
+
+        let raw_doi = record.raw_doi();
+        let problems = doi::problems(raw_doi.as_deref());
+
+        for problem in problems.iter() {
+            *self.counts.entry(*problem).or_insert(0) += 1;
+        }
+
+        let Some(raw_doi) = raw_doi else {
+            *self.missing.entry(record.source.clone()).or_insert(0) += 1;
+            return;
+        };
+
+        if !problems.is_empty() {
+            let prefix = doi::prefix(&doi::normalize(&raw_doi))
+                .unwrap_or(NO_PREFIX)
+                .to_string();
+
+            self.problems
+                .entry(record.source.clone())
+                .or_default()
+                .entry(prefix)
+                .or_default()
+                .push((raw_doi, problems));
+        }
+    }
+
+    /// Print the report to STDOUT: counts of each problem, then the DOIs with problems by source file and prefix.
+    /// DOIs are printed with Rust string escapes, so that control characters and whitespace are visible.
+    pub fn print(&self) {
+        println!("DOI validation:");
+        println!("Records checked: {}", self.checked);
+        for problem in DoiProblem::ALL {
+            println!(
+                "DOIs {}: {}",
+                problem,
+                self.counts.get(&problem).copied().unwrap_or(0)
+            );
+        }
+
+        let mut files: Vec<&Arc<Path>> = self.problems.keys().chain(self.missing.keys()).collect();
+        files.sort();
+        files.dedup();
+
+        for file in files {
+            println!();
+            println!("{}:", file.display());
+
+            if let Some(missing) = self.missing.get(file) {
+                println!("  Records without DOIs: {}", missing);
+            }
+
+            for (prefix, dois) in self.problems.get(file).into_iter().flatten() {
+                println!("  Prefix {} ({} with problems):", prefix, dois.len());
+                for (doi, problems) in dois {
+                    let problems: Vec<String> =
+                        problems.iter().map(|problem| problem.to_string()).collect();
+                    println!("    {:?} ({})", doi, problems.join(", "));
+                }
+            }
+        }
+    }
+}
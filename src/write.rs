@@ -1,17 +1,202 @@
-use std::{fs::File, io::BufWriter, path::PathBuf, sync::mpsc::Receiver};
+use std::{
+    fs::File,
+    io::{self, BufWriter},
+    path::Path,
+    sync::{mpsc::Receiver, Arc},
+};
 
-use flate2::{write::GzEncoder, Compression};
+use arrow_array::{
+    builder::{Int64Builder, StringBuilder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema};
+use parquet::{arrow::ArrowWriter, file::properties::WriterProperties};
 use serde_json::Value;
 
 use std::io::Write;
 
-pub(crate) fn write_chan_to_json_gz(
-    output_file: &PathBuf,
+use crate::{
+    compression::Compression, index::write_chan_to_indexed_jsonl, is_stdio,
+    model::flat::FlatRecord, sqlite::write_chan_to_sqlite, store::write_chan_to_redb,
+};
+
+/// Number of rows converted to Arrow at a time.
+const PARQUET_BATCH_SIZE: usize = 1024;
+
+/// Output file formats, chosen by file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    JsonLines(Compression),
+    Csv,
+    Parquet,
+    Redb,
+    Sqlite,
+}
+
+impl OutputFormat {
+    /// Choose the format from the file extension.
+    /// STDOUT, given as `-`, is always uncompressed JSON Lines.
+    pub fn from_path(path: &Path) -> anyhow::Result<OutputFormat> {
+        if is_stdio(path) {
+            return Ok(OutputFormat::JsonLines(Compression::None));
+        }
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy())
+            .unwrap_or_default();
+
+        for compression in Compression::ALL {
+            if name.ends_with(&format!(".jsonl{}", compression.extension())) {
+                return Ok(OutputFormat::JsonLines(compression));
+            }
+        }
+
+        if name.ends_with(".csv") {
+            Ok(OutputFormat::Csv)
+        } else if name.ends_with(".parquet") {
+            Ok(OutputFormat::Parquet)
+        } else if name.ends_with(".redb") {
+            Ok(OutputFormat::Redb)
+        } else if name.ends_with(".sqlite") || name.ends_with(".db") {
+            Ok(OutputFormat::Sqlite)
+        } else {
+            Err(anyhow::format_err!(
+                "Unrecognised output file extension for {:?}. Expected one of: {}",
+                path,
+                OUTPUT_EXTENSIONS.join(", ")
+            ))
+        }
+    }
+}
+
+/// Output file extensions that are understood.
+pub const OUTPUT_EXTENSIONS: &[&str] = &[
+    ".jsonl",
+    ".jsonl.gz",
+    ".jsonl.zst",
+    ".jsonl.xz",
+    ".jsonl.bz2",
+    ".csv",
+    ".parquet",
+    ".redb",
+    ".sqlite",
+    ".db",
+];
+
+/// Options for writing output files.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    /// Send progress messages to STDERR.
+    pub verbose: bool,
+
+    /// Compression level for compressed JSON Lines. If None, the default for the compression format.
+    pub compression_level: Option<u32>,
+
+    /// Maximum rows per Parquet row group.
+    pub row_group_size: usize,
+
+    /// Write gzip JSON Lines in blocks, with an index of DOIs for lookups. See [`crate::index`].
+    pub index: bool,
+}
+
+impl Default for WriteOptions {
+    fn default() -> WriteOptions {
+        WriteOptions {
+            verbose: false,
+            compression_level: None,
+            row_group_size: 100_000,
+            index: false,
+        }
+    }
+}
+
+/// Write records to the output file, in the format indicated by its extension.
+/// Returns the number of records skipped because they have no DOI, which only happens for outputs keyed by DOI.
+pub fn write_chan_to_file(
+    output_file: &Path,
     rx: Receiver<Value>,
+    options: &WriteOptions,
+) -> anyhow::Result<usize> {
+    let format = OutputFormat::from_path(output_file)?;
+
+    if options.index {
+        return match format {
+            OutputFormat::JsonLines(Compression::Gzip) if !is_stdio(output_file) => {
+                write_chan_to_indexed_jsonl(
+                    output_file,
+                    rx,
+                    options.compression_level,
+                    options.verbose,
+                )?;
+                Ok(0)
+            }
+            _ => Err(anyhow::format_err!(
+                "Only .jsonl.gz output files can be indexed, not {:?}",
+                output_file
+            )),
+        };
+    }
+
+    match format {
+        OutputFormat::JsonLines(compression) => write_chan_to_jsonl(
+            output_file,
+            rx,
+            compression,
+            options.compression_level,
+            options.verbose,
+        )?,
+        OutputFormat::Csv => write_chan_to_csv(output_file, rx, options.verbose)?,
+        OutputFormat::Parquet => {
+            write_chan_to_parquet(output_file, rx, options.row_group_size, options.verbose)?
+        }
+        OutputFormat::Redb => return write_chan_to_redb(output_file, rx, options.verbose),
+        OutputFormat::Sqlite => return write_chan_to_sqlite(output_file, rx, options.verbose),
+    }
+
+    Ok(0)
+}
+
+/// Write records as JSON Lines, one record per line, with optional compression.
+/// If `output_file` is `-` then write to STDOUT, stopping quietly if it's closed.
+pub fn write_chan_to_jsonl(
+    output_file: &Path,
+    rx: Receiver<Value>,
+    compression: Compression,
+    compression_level: Option<u32>,
     verbose: bool,
 ) -> anyhow::Result<()> {
-    let f = File::create(output_file)?;
-    let encoder = GzEncoder::new(f, Compression::best());
+    if is_stdio(output_file) {
+        let stdout = io::stdout().lock();
+        let result = write_chan_to_jsonl_writer(
+            stdout,
+            output_file,
+            rx,
+            compression,
+            compression_level,
+            verbose,
+        );
+
+        // The next stage of a pipeline may stop reading early, e.g. `head`.
+        match result {
+            Err(err) if is_broken_pipe(&err) => Ok(()),
+            result => result,
+        }
+    } else {
+        let f = BufWriter::new(File::create(output_file)?);
+        write_chan_to_jsonl_writer(f, output_file, rx, compression, compression_level, verbose)
+    }
+}
+
+fn write_chan_to_jsonl_writer<W: Write>(
+    f: W,
+    output_file: &Path,
+    rx: Receiver<Value>,
+    compression: Compression,
+    compression_level: Option<u32>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let encoder = compression.encoder(f, compression_level)?;
     let mut writer = BufWriter::new(encoder);
 
     let mut count: usize = 0;
@@ -20,10 +205,152 @@ pub(crate) fn write_chan_to_json_gz(
         writer.write_all(b"\n")?;
 
         count += 1;
-        if verbose && count % 10000 == 0 {
+        if verbose && count.is_multiple_of(10000) {
+            eprintln!("Written {} entries to {:?}", count, output_file);
+        }
+    }
+
+    writer
+        .into_inner()
+        .map_err(|err| err.into_error())?
+        .finish()?
+        .flush()?;
+
+    Ok(())
+}
+
+/// Whether an error was caused by the reader of a pipe going away.
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    let kind = if let Some(err) = err.downcast_ref::<io::Error>() {
+        Some(err.kind())
+    } else {
+        err.downcast_ref::<serde_json::Error>()
+            .and_then(|err| err.io_error_kind())
+    };
+
+    kind == Some(io::ErrorKind::BrokenPipe)
+}
+
+/// Write records to a CSV file, one row per record, with the columns of [`FlatRecord`].
+pub fn write_chan_to_csv(
+    output_file: &Path,
+    rx: Receiver<Value>,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    let mut writer = csv::Writer::from_path(output_file)?;
+
+    let mut count: usize = 0;
+    for entry in rx.iter() {
+        writer.serialize(FlatRecord::from_value(&entry))?;
+
+        count += 1;
+        if verbose && count.is_multiple_of(10000) {
             eprintln!("Written {} entries to {:?}", count, output_file);
         }
     }
 
+    writer.flush()?;
+
     Ok(())
 }
+
+/// Write records to a Parquet file, one row per record, with the columns of [`FlatRecord`].
+/// Each row includes the full JSON, and a row group is buffered in memory before being written,
+/// so `row_group_size` should be modest.
+pub fn write_chan_to_parquet(
+    output_file: &Path,
+    rx: Receiver<Value>,
+    row_group_size: usize,
+    verbose: bool,
+) -> anyhow::Result<()> {
+    // The Parquet writer panics on a row group size of 0.
+    if row_group_size == 0 {
+        return Err(anyhow::format_err!("Row group size must be at least 1"));
+    }
+
+    let schema = Arc::new(flat_record_schema());
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(row_group_size)
+        .build();
+
+    let f = File::create(output_file)?;
+    let mut writer = ArrowWriter::try_new(f, schema.clone(), Some(properties))?;
+
+    let mut batch: Vec<FlatRecord> = Vec::with_capacity(PARQUET_BATCH_SIZE);
+    let mut count: usize = 0;
+    for entry in rx.iter() {
+        batch.push(FlatRecord::from_value(&entry));
+
+        if batch.len() >= PARQUET_BATCH_SIZE {
+            writer.write(&flat_records_to_batch(&batch, &schema)?)?;
+            batch.clear();
+        }
+
+        count += 1;
+        if verbose && count.is_multiple_of(10000) {
+            eprintln!("Written {} entries to {:?}", count, output_file);
+        }
+    }
+
+    if !batch.is_empty() {
+        writer.write(&flat_records_to_batch(&batch, &schema)?)?;
+    }
+
+    writer.close()?;
+
+    Ok(())
+}
+
+/// Arrow schema corresponding to [`FlatRecord`].
+fn flat_record_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("doi", DataType::Utf8, true),
+        Field::new("agency", DataType::Utf8, true),
+        Field::new("resource_type", DataType::Utf8, true),
+        Field::new("publication_year", DataType::Int64, true),
+        Field::new("publisher", DataType::Utf8, true),
+        Field::new("title", DataType::Utf8, true),
+        Field::new("creator_count", DataType::Int64, true),
+        Field::new("reference_count", DataType::Int64, true),
+        Field::new("license", DataType::Utf8, true),
+        Field::new("updated", DataType::Utf8, true),
+        Field::new("json", DataType::Utf8, false),
+    ])
+}
+
+fn flat_records_to_batch(
+    records: &[FlatRecord],
+    schema: &Arc<Schema>,
+) -> anyhow::Result<RecordBatch> {
+    let strings = |f: fn(&FlatRecord) -> Option<&str>| -> ArrayRef {
+        let mut builder = StringBuilder::new();
+        for record in records {
+            builder.append_option(f(record));
+        }
+        Arc::new(builder.finish())
+    };
+
+    let ints = |f: fn(&FlatRecord) -> Option<i64>| -> ArrayRef {
+        let mut builder = Int64Builder::new();
+        for record in records {
+            builder.append_option(f(record));
+        }
+        Arc::new(builder.finish())
+    };
+
+    let columns = vec![
+        strings(|r| r.doi.as_deref()),
+        strings(|r| r.agency.as_deref()),
+        strings(|r| r.resource_type.as_deref()),
+        ints(|r| r.publication_year),
+        strings(|r| r.publisher.as_deref()),
+        strings(|r| r.title.as_deref()),
+        ints(|r| r.creator_count),
+        ints(|r| r.reference_count),
+        strings(|r| r.license.as_deref()),
+        strings(|r| r.updated.as_deref()),
+        strings(|r| Some(r.json.as_str())),
+    ];
+
+    Ok(RecordBatch::try_new(schema.clone(), columns)?)
+}